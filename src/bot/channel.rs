@@ -0,0 +1,127 @@
+use crate::{
+    bot::discord_api::{ self, Reply },
+    error::Result,
+};
+
+use serenity::{
+    prelude::*,
+    model::id::{ ChannelId, MessageId },
+};
+
+/// The operations the bot performs on the messages of a channel.
+pub trait ChannelOps {
+    fn edit_message(&self, ctx: &Context, channel_id: u64, message_id: u64, content: &str) -> Result<()>;
+    fn delete_message(&self, ctx: &Context, channel_id: u64, message_id: u64) -> Result<()>;
+    /// Sends a message with attachments, given as their bytes and file name.
+    fn send_files(&self, ctx: &Context, channel_id: u64, files: &[(&[u8], &str)], content: &str, reply: Option<&Reply>) -> Result<()>;
+    fn send_message(&self, ctx: &Context, channel_id: u64, content: &str, reply: Option<&Reply>) -> Result<()>;
+    /// Returns what a message replies to, if it is a reply.
+    fn reply_of(&self, ctx: &Context, channel_id: u64, message_id: u64) -> Result<Option<Reply>>;
+}
+
+/// Performs the operations on Discord.
+pub struct SerenityChannel {
+    token: String, // Replies are sent with the REST API directly
+}
+
+impl SerenityChannel {
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: token.to_owned(),
+        }
+    }
+}
+
+impl ChannelOps for SerenityChannel {
+    fn edit_message(&self, ctx: &Context, channel_id: u64, message_id: u64, content: &str) -> Result<()> {
+        ChannelId(channel_id).edit_message(ctx, MessageId(message_id), |m| m.content(content))?;
+        Ok(())
+    }
+
+    fn delete_message(&self, ctx: &Context, channel_id: u64, message_id: u64) -> Result<()> {
+        ChannelId(channel_id).delete_message(ctx, MessageId(message_id))?;
+        Ok(())
+    }
+
+    fn send_files(&self, ctx: &Context, channel_id: u64, files: &[(&[u8], &str)], content: &str, reply: Option<&Reply>) -> Result<()> {
+        if let Some(reply) = reply {
+            return discord_api::send_reply(&self.token, channel_id, content, files, reply);
+        }
+        ChannelId(channel_id).send_files(ctx, files.iter().cloned(), |m| m.content(content))?;
+        Ok(())
+    }
+
+    fn send_message(&self, ctx: &Context, channel_id: u64, content: &str, reply: Option<&Reply>) -> Result<()> {
+        if let Some(reply) = reply {
+            return discord_api::send_reply(&self.token, channel_id, content, &[], reply);
+        }
+        ChannelId(channel_id).send_message(ctx, |m| m.content(content))?;
+        Ok(())
+    }
+
+    fn reply_of(&self, _ctx: &Context, channel_id: u64, message_id: u64) -> Result<Option<Reply>> {
+        discord_api::reply_of(&self.token, channel_id, message_id)
+    }
+}
+
+/// An operation recorded by `RecordingChannel`.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelAction {
+    Edit { channel_id: u64, message_id: u64, content: String },
+    Delete { channel_id: u64, message_id: u64 },
+    SendFiles { channel_id: u64, files: Vec<String>, content: String, reply: Option<Reply> }, // File names of the attachments
+    SendMessage { channel_id: u64, content: String, reply: Option<Reply> },
+}
+
+/// Records the operations instead of performing them, for the tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingChannel {
+    actions: std::sync::Mutex<Vec<ChannelAction>>,
+    payloads: std::sync::Mutex<Vec<Vec<u8>>>, // Bytes of every file sent
+    pub reply: Option<Reply>, // What every message replies to
+}
+
+#[cfg(test)]
+impl RecordingChannel {
+    /// The operations recorded so far, in order.
+    pub fn actions(&self) -> Vec<ChannelAction> {
+        self.actions.lock().unwrap().clone()
+    }
+
+    /// The bytes of the files sent so far, in order.
+    pub fn payloads(&self) -> Vec<Vec<u8>> {
+        self.payloads.lock().unwrap().clone()
+    }
+
+    fn record(&self, action: ChannelAction) -> Result<()> {
+        self.actions.lock().unwrap().push(action);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl ChannelOps for RecordingChannel {
+    fn edit_message(&self, _ctx: &Context, channel_id: u64, message_id: u64, content: &str) -> Result<()> {
+        self.record(ChannelAction::Edit { channel_id, message_id, content: content.to_owned() })
+    }
+
+    fn delete_message(&self, _ctx: &Context, channel_id: u64, message_id: u64) -> Result<()> {
+        self.record(ChannelAction::Delete { channel_id, message_id })
+    }
+
+    fn send_files(&self, _ctx: &Context, channel_id: u64, files: &[(&[u8], &str)], content: &str, reply: Option<&Reply>) -> Result<()> {
+        self.payloads.lock().unwrap().extend(files.iter().map(|(bytes, _name)| bytes.to_vec()));
+        let files = files.iter().map(|(_bytes, name)| (*name).to_owned()).collect();
+        self.record(ChannelAction::SendFiles { channel_id, files, content: content.to_owned(), reply: reply.cloned() })
+    }
+
+    fn send_message(&self, _ctx: &Context, channel_id: u64, content: &str, reply: Option<&Reply>) -> Result<()> {
+        self.record(ChannelAction::SendMessage { channel_id, content: content.to_owned(), reply: reply.cloned() })
+    }
+
+    fn reply_of(&self, _ctx: &Context, _channel_id: u64, _message_id: u64) -> Result<Option<Reply>> {
+        Ok(self.reply.clone())
+    }
+}
@@ -0,0 +1,149 @@
+use crate::Result;
+
+use serde::{ Serialize, Deserialize };
+use reqwest::blocking::multipart;
+
+const API_URL: &str = "https://discord.com/api/v8";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageReference {
+    pub message_id: Option<String>,
+    pub channel_id: Option<String>,
+    pub guild_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawUser {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Attachment {
+    pub url: String,
+    pub filename: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawMessage {
+    pub author: Option<RawUser>,
+    pub message_reference: Option<MessageReference>,
+    pub referenced_message: Option<Box<RawMessage>>,
+    #[serde(default)]
+    pub mentions: Vec<RawUser>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+/// A message to reply to, and whether its author is pinged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub guild_id: Option<u64>,
+    pub mention: bool,
+}
+
+#[derive(Serialize)]
+struct OutgoingReference {
+    message_id: String,
+    channel_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    guild_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AllowedMentions {
+    parse: Vec<&'static str>,
+    replied_user: bool,
+}
+
+#[derive(Serialize)]
+struct OutgoingMessage<'a> {
+    content: &'a str,
+    message_reference: OutgoingReference,
+    allowed_mentions: AllowedMentions,
+}
+
+/// Fetches a message from the REST API, for the fields that serenity does not expose such as replies.
+pub fn get_message(token: &str, channel_id: u64, message_id: u64) -> Result<RawMessage> {
+    let url = format!("{}/channels/{}/messages/{}", API_URL, channel_id, message_id);
+    let res = reqwest::blocking::Client::new()
+                .get(&url)
+                .header(reqwest::header::AUTHORIZATION, token)
+                .send()?
+                .error_for_status()?;
+    Ok(res.json()?)
+}
+
+/// Returns the message that a message replies to, if it is a reply.
+pub fn referenced_message(token: &str, channel_id: u64, message_id: u64) -> Result<Option<RawMessage>> {
+    let reference = match get_message(token, channel_id, message_id)?.message_reference {
+        Some(reference) => reference,
+        None => return Ok(None),
+    };
+
+    let channel_id = reference.channel_id.and_then(|id| id.parse().ok()).unwrap_or(channel_id);
+    match reference.message_id.and_then(|id| id.parse().ok()) {
+        Some(message_id) => Ok(Some(get_message(token, channel_id, message_id)?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns what a message replies to, if it is a reply.
+/// The author of the replied message is pinged if they are among the mentions of the message.
+pub fn reply_of(token: &str, channel_id: u64, message_id: u64) -> Result<Option<Reply>> {
+    let message = get_message(token, channel_id, message_id)?;
+    let reference = match message.message_reference {
+        Some(reference) => reference,
+        None => return Ok(None),
+    };
+    let message_id = match reference.message_id.and_then(|id| id.parse().ok()) {
+        Some(message_id) => message_id,
+        None => return Ok(None),
+    };
+
+    let mentions = message.mentions;
+    let replied_author = message.referenced_message.and_then(|replied| replied.author).map(|author| author.id);
+    let mention = replied_author.map_or(false, |author| mentions.iter().any(|user| user.id == author));
+    Ok(Some(Reply {
+        channel_id: reference.channel_id.and_then(|id| id.parse().ok()).unwrap_or(channel_id),
+        message_id,
+        guild_id: reference.guild_id.and_then(|id| id.parse().ok()),
+        mention,
+    }))
+}
+
+/// Sends a message replying to another, with attachments given as their bytes and file name.
+/// Serenity cannot send replies, so this goes through the REST API directly.
+pub fn send_reply(token: &str, channel_id: u64, content: &str, files: &[(&[u8], &str)], reply: &Reply) -> Result<()> {
+    let payload = OutgoingMessage {
+        content,
+        message_reference: OutgoingReference {
+            message_id: reply.message_id.to_string(),
+            channel_id: reply.channel_id.to_string(),
+            guild_id: reply.guild_id.map(|id| id.to_string()),
+        },
+        allowed_mentions: AllowedMentions {
+            parse: vec![ "users", "roles", "everyone" ],
+            replied_user: reply.mention,
+        },
+    };
+
+    let url = format!("{}/channels/{}/messages", API_URL, channel_id);
+    let req = reqwest::blocking::Client::new()
+                .post(&url)
+                .header(reqwest::header::AUTHORIZATION, token);
+    let req = if files.is_empty() {
+        req.json(&payload)
+    } else {
+        let mut form = multipart::Form::new().text("payload_json", serde_json::to_string(&payload)?);
+        for (idx, (bytes, file_name)) in files.iter().enumerate() {
+            let part = multipart::Part::bytes(bytes.to_vec()).file_name((*file_name).to_owned());
+            form = form.part(format!("file{}", idx), part);
+        }
+        req.multipart(form)
+    };
+    req.send()?.error_for_status()?;
+    Ok(())
+}
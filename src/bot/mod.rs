@@ -1,35 +1,59 @@
 pub mod user;
 pub use user::{ User, UserSettings, UserSettingsKey };
+pub mod discord_api;
+pub use discord_api::Reply;
+pub mod planner;
+pub mod channel;
+pub use channel::{ ChannelOps, SerenityChannel };
+
+#[cfg(test)]
+mod tests;
 
 use std::sync::Arc;
 
 use crate::{
     config::Config,
+    bot::planner::Action,
     commands::{ self, Command },
+    transformers::{ self, Flow, MessageDraft, MessageTransformer },
     error::{ Error, ErrorKind, Result },
+    stats::UsageStats,
     emote_manager::{ Emote, EmoteManager },
 };
 
 use serenity::{
     prelude::*,
-    http::AttachmentType,
     framework::standard::StandardFramework,
-    builder::{ EditMessage, CreateMessage },
-    model::{ channel::Message, gateway::Ready, event::MessageUpdateEvent },
+    model::{ channel::{ Message, MessageType }, gateway::Ready, event::MessageUpdateEvent },
 };
 
 pub struct Bot {
     pub user: User,
+    private_emotes: Option<Arc<EmoteManager>>, // Searched before the shared library
     commands: Vec<Box<dyn Command + Send + Sync>>,
+    transformers: Vec<Box<dyn MessageTransformer + Send + Sync>>, // Run in order on the messages that are not commands
+    channel: Arc<dyn ChannelOps + Send + Sync>, // Where the messages are edited, deleted and sent
 }
 
 impl Bot {
-    pub fn new(user: User) -> Self {
+    pub fn new(user: User, private_emotes: Option<Arc<EmoteManager>>) -> Self {
+        let channel = Arc::new(SerenityChannel::new(&user.token));
+        Self::with_channel(user, private_emotes, channel)
+    }
+
+    pub fn with_channel(user: User, private_emotes: Option<Arc<EmoteManager>>, channel: Arc<dyn ChannelOps + Send + Sync>) -> Self {
         Self {
+            transformers: transformers::build(&user.transformers),
             user,
+            private_emotes,
+            channel,
             commands: vec![
                 commands::Palette::boxed(),
                 commands::Spoiler::boxed(),
+                commands::Stats::boxed(),
+                commands::Add::boxed(),
+                commands::Rename::boxed(),
+                commands::Remove::boxed(),
             ],
         }
     }
@@ -67,174 +91,123 @@ impl EventHandler for Bot {
 }
 
 impl Bot {
-    pub fn handle_message(&self, ctx: Context, mut msg: Option<&mut Message>, event: Option<&MessageUpdateEvent>) -> Result<()> {
-        if self.handle_message_internal(&ctx, &mut msg, &event)? {
+    pub fn handle_message(&self, ctx: Context, msg: Option<&mut Message>, event: Option<&MessageUpdateEvent>) -> Result<()> {
+        if self.handle_message_internal(&ctx, &msg, &event)? {
             self.delete_message(&ctx, &msg, &event)?;
         }
         Ok(())
     }
 
-    fn handle_message_internal(&self, ctx: &Context, msg: &mut Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<bool> {
-        if self.handle_commands(&ctx, &msg, event)? {
+    fn handle_message_internal(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<bool> {
+        if self.handle_commands(ctx, msg, event)? {
             return Ok(true);
         }
 
-        let settings = {
+        let spoiler_mode = {
             let data = ctx.data.read();
-            data.get::<UserSettingsKey>().ok_or_else(|| Error::new(ErrorKind::DataGet))?.clone()
+            data.get::<UserSettingsKey>().ok_or_else(|| Error::new(ErrorKind::DataGet))?.spoiler_mode(self.channel_id(msg, event))
         };
-        let contents = self.message_content(&msg, event);
-        if settings.spoiler_mode(self.channel_id(msg, event)) && !contents.trim().is_empty() {
-            let mut with_spoiler = contents;
-            if commands::Spoiler::spoilerize(&mut with_spoiler) {
-                self.edit_message(ctx, msg, event, |m| m.content(with_spoiler))?;
-                return Ok(false);
+        let content = self.message_content(msg, event);
+        let mut draft = MessageDraft::new(content.clone(), spoiler_mode);
+        for transformer in self.transformers.iter() {
+            if transformer.transform(self, ctx, msg, event, &mut draft)? == Flow::Stop {
+                break;
             }
         }
 
-        self.handle_text_emotes(ctx, msg, event)?;
-        if self.handle_emotes(ctx, &settings, msg, event)? {
-            return Ok(true);
-        }
-
-        Ok(false)
+        self.apply_draft(ctx, msg, event, &content, draft)
     }
 
-    fn handle_emotes(&self, ctx: &Context, settings: &UserSettings, msg: &mut Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<bool> {
-        let prefix = &self.user.emote_prefix;
-        let twitch_prefix = &self.user.twitch_emote_prefix;
-        let content = self.message_content(&msg, event);
-        let spoiler_mode = settings.spoiler_mode(self.channel_id(msg, event));
-
-        if !content.contains(prefix) && !content.contains(twitch_prefix) &&
-            !prefix.is_empty() && !twitch_prefix.is_empty() {
+    /// Updates the message with the result of the pipeline, following the actions planned by `planner::plan`.
+    /// Returns whether the original message should be deleted.
+    fn apply_draft(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, original: &str, draft: MessageDraft) -> Result<bool> {
+        let has_attachments = self.message_has_attachments(msg, event);
+        let upload_limit = ctx.data.read().get::<EmoteManager>().map_or(u64::MAX, EmoteManager::upload_limit);
+        let actions = planner::plan(draft.segments, original, draft.spoiler_mode, has_attachments, upload_limit);
+        if actions.is_empty() {
             return Ok(false);
         }
 
-        let re = format!(r"(^|\s+)(?P<prefix>{}|{})(?P<emote>\w*)", prefix, twitch_prefix);
-        let re = regex::Regex::new(&re).unwrap();
-
-        let splits: Vec<_> = re.split(&content).collect();
-        let captures: Vec<_> = re.captures_iter(&content).collect();
-        let n_splits = splits.len();
-        let n_captures = captures.len();
-
-        if n_captures == 0 || captures.iter().all(|cap| cap["emote"].trim().is_empty()) {
-            return Ok(false);
-        }
+        // The first message sent in place of a deleted reply replies to the same message
+        let mut reply = if actions.iter().any(|action| matches!(action, Action::Delete)) {
+            self.reply(ctx, msg, event)
+        } else {
+            None
+        };
 
         let data = ctx.data.read();
-        let mngr = data.get::<EmoteManager>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
-
-        struct EmoteMessage<'a> {
-            pub content: String,
-            pub capture: String,
-            pub whitespace: String,
-            pub emote: Option<&'a Emote>,
-        }
-
-        let twitch_emotes = splits
-                                .iter().zip(captures.iter())
-                                .map(|(_split, capture)| if &capture["prefix"] == twitch_prefix {
-                                    mngr.find_twitch_emote(&capture["emote"]).unwrap_or_else(|_err| None)
-                                } else {
-                                    None
-                                })
-                                .collect::<Vec<_>>();
-        let mut messages = splits
-                            .iter()
-                            .zip(captures.iter())
-                            .zip(twitch_emotes.iter())
-                            .map(|((split, capture), twitch_emote)| EmoteMessage {
-                                content: (*split).to_string(),
-                                capture: capture[0].to_owned(),
-                                whitespace: capture[1].to_owned(),
-                                emote: if &capture["prefix"] == prefix {
-                                    mngr.find_emote_by_name(&capture["emote"])
-                                } else if &capture["prefix"] == twitch_prefix {
-                                    twitch_emote.as_ref()
-                                } else {
-                                    None
-                                },
-                            })
-                            .collect::<Vec<_>>();
-        if messages.iter().all(|msg| msg.emote.is_none()) {
-            // Return if all emotes are invalid
-            return Ok(false);
+        let stats = data.get::<UsageStats>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+        let mut delete = false;
+        for action in actions {
+            match action {
+                Action::Edit(content) => self.edit_message(ctx, msg, event, &content)?,
+                Action::SendFiles(emotes, content) => {
+                    let mngr = data.get::<EmoteManager>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+                    let payloads = emotes
+                                    .iter()
+                                    .map(|queued| self.emote_owner(mngr, &queued.emote).payload(&queued.emote))
+                                    .collect::<Result<Vec<_>>>()?;
+                    let files = emotes
+                                    .iter()
+                                    .zip(payloads.iter())
+                                    .map(|(queued, payload)| queued.emote.as_attachment(payload))
+                                    .collect::<Vec<_>>();
+                    self.send_files(ctx, msg, event, &files, &content, reply.take().as_ref())?;
+                    for name in emotes.iter().flat_map(|queued| queued.usage.iter()) {
+                        self.record_usage(stats, name);
+                    }
+                },
+                Action::SendText(content) => self.send_reply(ctx, msg, event, &content, reply.take().as_ref())?,
+                Action::Delete => delete = true,
+            };
         }
-        if n_splits > n_captures {
-            // If there is text after the last emote, add the last bit of text that was not taken by the .iter().zip()
-            messages.push(EmoteMessage {
-                content: splits[n_splits - 1].to_string(),
-                capture: String::new(),
-                whitespace: String::new(),
-                emote: None,
-            });
+        for name in draft.usage.iter() {
+            self.record_usage(stats, name);
         }
+        Ok(delete)
+    }
 
-        let has_attachments = self.message_has_attachments(&msg, event);
-        let mut content = String::new();
-        let mut first = true;
-        let mut delete = true;
-        for emote_msg in messages.iter() {
-            content.push_str(&emote_msg.content);
-
-            if let Some(emote) = emote_msg.emote {
-                let trimmed = content.trim();
-                let empty = trimmed.is_empty() || trimmed == "||"; // Check if the message was empty before (possibly) applying the spoiler pipes
-                content.push_str(&emote_msg.whitespace);
-                if spoiler_mode {
-                    commands::Spoiler::spoilerize(&mut content);
-                }
-
-                if first && !empty {
-                    self.edit_message(ctx, msg, event, |m| m.content(&content))?;
-                    if !empty {
-                        delete = false;
-                    }
-                    first = false;
-                    content.clear();
-                }
-                self.send_files(ctx, &msg, event, vec![emote.as_attachment()], |m| m.content(&content))?;
-                content.clear();
-            } else {
-                content.push_str(&emote_msg.capture);
-            }
-        }
-        if !content.trim().is_empty() && (!spoiler_mode || content.trim() != "||") {
-            if spoiler_mode {
-                commands::Spoiler::spoilerize(&mut content);
-            }
-            self.send_message(ctx, &msg, event, |m| m.content(&content))?;
+    /// What the message replies to, if it is a reply.
+    /// Regular messages are not replies, the REST API is only asked about the other ones
+    /// and about the updates that the gateway sent without their type.
+    /// The message is still updated if this cannot be found, so errors are only logged.
+    fn reply(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Option<Reply> {
+        let kind = match (msg, event) {
+            (Some(msg), _) => Some(msg.kind),
+            (None, Some(event)) => event.kind,
+            (None, None) => return None,
+        };
+        if kind == Some(MessageType::Regular) {
+            return None;
         }
 
-        if delete && has_attachments {
-            self.edit_message(ctx, msg, event, |m| m.content(""))?;
+        match self.channel.reply_of(ctx, self.channel_id(msg, event), self.message_id(msg, event)) {
+            Ok(reply) => reply,
+            Err(err) => {
+                log::warn!("Could not get the message replied to: {}", err);
+                None
+            },
         }
-        Ok(delete && !has_attachments)
     }
 
-    fn handle_text_emotes(&self, ctx: &Context, msg: &mut Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<()> {
-        let prefix = &self.user.text_emote_prefix;
-        if !self.message_content(&msg, event).contains(prefix) || prefix.is_empty() {
-            return Ok(());
+    /// Counts a use of an emote, the message was already sent so errors are only logged.
+    pub fn record_usage(&self, stats: &UsageStats, name: &str) {
+        if let Err(err) = stats.record(self.user.discord_id, name) {
+            log::warn!("Could not record the use of emote \"{}\": {}", name, err);
         }
+    }
 
-        let data = ctx.data.read();
-        let mngr = data.get::<EmoteManager>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
-        let content = self.message_content(&msg, event);
-        let mut edited = content.clone();
+    /// The private emotes of the user, searched before the shared library.
+    pub fn private_emotes(&self) -> Option<&Arc<EmoteManager>> {
+        self.private_emotes.as_ref()
+    }
 
-        for (triggers, emote) in mngr.text_emotes() {
-            for trigger in triggers {
-                edited = edited.replace(&format!("{}{}", prefix, trigger), emote);
-            }
-        }
-        if edited != content {
-            self.edit_message(ctx, msg, event, |m| m.content(edited))?;
+    /// The manager whose library holds the file of the emote, it caches and maps the file for its own watcher.
+    pub fn emote_owner<'a>(&'a self, shared: &'a EmoteManager, emote: &Emote) -> &'a EmoteManager {
+        match self.private_emotes() {
+            Some(private_emotes) if private_emotes.owns(emote) => private_emotes,
+            _ => shared,
         }
-
-        Ok(())
     }
 
     fn handle_commands(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<bool> {
@@ -255,24 +228,18 @@ impl Bot {
         Ok(false)
     }
 
-    pub fn edit_message<F>(&self, ctx: &Context, msg: &mut Option<&mut Message>, event: &Option<&MessageUpdateEvent>, f: F) -> serenity::Result<()>
-    where F: FnOnce(&mut EditMessage) -> &mut EditMessage {
-
-        if let Some(msg) = msg {
-            return msg.edit(ctx, f);
-        } else if let Some(event) = event {
-            event.channel_id.edit_message(ctx, event.id, f)?;
+    pub fn edit_message(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, content: &str) -> Result<()> {
+        if msg.is_none() && event.is_none() {
+            return Ok(());
         }
-        Ok(())
+        self.channel.edit_message(ctx, self.channel_id(msg, event), self.message_id(msg, event), content)
     }
 
-    pub fn delete_message(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> serenity::Result<()> {
-        if let Some(msg) = msg {
-            return msg.channel_id.delete_message(&ctx, msg.id);
-        } else if let Some(event) = event {
-            return event.channel_id.delete_message(&ctx, event.id);
+    pub fn delete_message(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<()> {
+        if msg.is_none() && event.is_none() {
+            return Ok(());
         }
-        Ok(())
+        self.channel.delete_message(ctx, self.channel_id(msg, event), self.message_id(msg, event))
     }
 
     pub fn message_content(&self, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> String {
@@ -286,28 +253,24 @@ impl Bot {
         "".into()
     }
 
-    pub fn send_files<'a, It, T, F>(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, files: It, f: F) -> serenity::Result<Option<Message>>
-    where T: Into<AttachmentType<'a>>,
-            It: IntoIterator<Item = T>,
-            for <'b> F: FnOnce(&'b mut CreateMessage<'a>) -> &'b mut CreateMessage<'a> {
-
-        if let Some(msg) = msg {
-            return Ok(Some(msg.channel_id.send_files(ctx, files, f)?));
-        } else if let Some(event) = event {
-            return Ok(Some(event.channel_id.send_files(ctx, files, f)?));
+    /// Sends a message with attachments in the channel of the message, given as their bytes and file name.
+    pub fn send_files(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, files: &[(&[u8], &str)], content: &str, reply: Option<&Reply>) -> Result<()> {
+        if msg.is_none() && event.is_none() {
+            return Ok(());
         }
-        Ok(None)
+        self.channel.send_files(ctx, self.channel_id(msg, event), files, content, reply)
     }
 
-    pub fn send_message<'a, F>(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, f: F) -> serenity::Result<Option<Message>>
-    where for <'b> F: FnOnce(&'b mut CreateMessage<'a>) -> &'b mut CreateMessage<'a> {
+    pub fn send_message(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, content: &str) -> Result<()> {
+        self.send_reply(ctx, msg, event, content, None)
+    }
 
-        if let Some(msg) = msg {
-            return Ok(Some(msg.channel_id.send_message(ctx, f)?));
-        } else if let Some(event) = event {
-            return Ok(Some(event.channel_id.send_message(ctx, f)?));
+    /// Sends a message in the channel of the message, replying to `reply` if given.
+    pub fn send_reply(&self, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, content: &str, reply: Option<&Reply>) -> Result<()> {
+        if msg.is_none() && event.is_none() {
+            return Ok(());
         }
-        Ok(None)
+        self.channel.send_message(ctx, self.channel_id(msg, event), content, reply)
     }
 
     pub fn message_has_attachments(&self, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> bool {
@@ -323,6 +286,16 @@ impl Bot {
         }
     }
 
+    pub fn message_id(&self, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> u64 {
+        if let Some(msg) = msg {
+            msg.id.0
+        } else if let Some(event) = event {
+            event.id.0
+        } else {
+            0
+        }
+    }
+
     pub fn channel_id(&self, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> u64 {
         if let Some(msg) = msg {
             msg.channel_id.0
@@ -333,8 +306,8 @@ impl Bot {
         }
     }
 
-    pub fn start(user: User, config: Arc<Config>, emotes_mngr: Arc<EmoteManager>) -> Result<Client> {
-        let bot = Bot::new(user.clone());
+    pub fn start(user: User, config: Arc<Config>, emotes_mngr: Arc<EmoteManager>, private_emotes: Option<Arc<EmoteManager>>, stats: Arc<UsageStats>) -> Result<Client> {
+        let bot = Bot::new(user.clone(), private_emotes);
         let mut client = Client::new(&user.token, bot)?;
         client.with_framework(StandardFramework::new()
             .configure(|c| c
@@ -347,6 +320,7 @@ impl Bot {
             let mut data = client.data.write();
             data.insert::<Config>(config);
             data.insert::<EmoteManager>(emotes_mngr);
+            data.insert::<UsageStats>(stats);
             data.insert::<UserSettingsKey>(UserSettings::default());
         }
 
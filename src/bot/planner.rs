@@ -0,0 +1,305 @@
+use crate::{
+    modifiers,
+    commands::Spoiler,
+};
+
+use regex::Regex;
+
+/// Most attachments Discord accepts in a single message.
+pub const MAX_ATTACHMENTS: usize = 10;
+
+/// A part of a message, emotes are sent as attachments between the text parts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment<E> {
+    Text(String),
+    Emote(E),
+}
+
+/// An emote sent as an attachment, batches of attachments must fit in the upload limit.
+pub trait Attachment {
+    /// Size of the file sent, in bytes.
+    fn size(&self) -> u64;
+}
+
+/// What the bot does to a message on Discord, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action<E> {
+    Edit(String), // Replaces the content of the original message
+    SendFiles(Vec<E>, String), // Emotes and the text sent along with them
+    SendText(String),
+    Delete, // Deletes the original message
+}
+
+/// Matches the emotes typed with one of the prefixes, e.g. `>pepe/sad+kappa:flip`.
+pub fn emote_regex(prefixes: &[&str]) -> Regex {
+    // Longest prefixes first so that a prefix starting with another one can still be matched
+    let mut prefixes = prefixes
+                            .iter()
+                            .map(|prefix| regex::escape(prefix))
+                            .collect::<Vec<_>>();
+    prefixes.sort_by_key(|prefix| std::cmp::Reverse(prefix.len()));
+    let re = format!(r"(^|\s+)(?P<prefix>{})(?P<emote>(?:\w+/)*\w*(?:\+(?:\w+/)*\w+)*)(?P<modifiers>{})", prefixes.join("|"), modifiers::PATTERN);
+    Regex::new(&re).unwrap()
+}
+
+/// Splits a text at the emotes that `lookup` finds from their prefix, name and modifiers.
+/// The emotes that are not found are left in the text.
+pub fn split<E, F>(text: &str, re: &Regex, mut lookup: F) -> Vec<Segment<E>>
+where F: FnMut(&str, &str, &str) -> Option<E> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut rest = 0;
+
+    for capture in re.captures_iter(text) {
+        let whole = capture.get(0).unwrap();
+        current.push_str(&text[rest..whole.start()]);
+        rest = whole.end();
+
+        match lookup(&capture["prefix"], &capture["emote"], &capture["modifiers"]) {
+            Some(emote) => {
+                current.push_str(&capture[1]); // The whitespace before the emote
+                segments.push(Segment::Text(std::mem::take(&mut current)));
+                segments.push(Segment::Emote(emote));
+            },
+            None => current.push_str(whole.as_str()),
+        };
+    }
+
+    current.push_str(&text[rest..]);
+    segments.push(Segment::Text(current));
+    segments
+}
+
+/// Turns the segments of a message into the actions that update it on Discord.
+/// The original message keeps the text before the first emote, the rest is sent in new messages
+/// and the original message is deleted if nothing is left in it.
+/// Consecutive emotes with no text between them are sent together, up to `MAX_ATTACHMENTS` per message
+/// and as long as their total size stays within `upload_limit`.
+/// In spoiler mode every text sent is wrapped in spoiler pipes.
+pub fn plan<E: Attachment>(segments: Vec<Segment<E>>, original: &str, spoiler_mode: bool, has_attachments: bool, upload_limit: u64) -> Vec<Action<E>> {
+    let has_emotes = segments.iter().any(|segment| matches!(segment, Segment::Emote(_)));
+    if !has_emotes {
+        let text = segments
+                        .into_iter()
+                        .filter_map(|segment| match segment {
+                            Segment::Text(text) => Some(text),
+                            Segment::Emote(_) => None,
+                        })
+                        .collect::<String>();
+        return if text != original {
+            vec![ Action::Edit(text) ]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let mut actions = Vec::new();
+    let mut content = String::new();
+    let mut first = true;
+    let mut delete = true;
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => content.push_str(&text),
+            Segment::Emote(emote) => {
+                let trimmed = content.trim();
+                let empty = trimmed.is_empty() || trimmed == "||"; // Check if the message was empty before (possibly) applying the spoiler pipes
+                if empty {
+                    content.clear();
+                    if let Some(Action::SendFiles(emotes, _)) = actions.last_mut() {
+                        let size = emotes.iter().map(Attachment::size).sum::<u64>();
+                        if emotes.len() < MAX_ATTACHMENTS && size.saturating_add(emote.size()) <= upload_limit {
+                            emotes.push(emote);
+                            continue;
+                        }
+                    }
+                } else if spoiler_mode {
+                    Spoiler::spoilerize(&mut content);
+                }
+
+                // Once an emote was sent, editing the original message would put its text before the emote
+                if first && !empty {
+                    actions.push(Action::Edit(std::mem::take(&mut content)));
+                    delete = false;
+                }
+                first = false;
+                actions.push(Action::SendFiles(vec![ emote ], std::mem::take(&mut content)));
+            },
+        };
+    }
+    if !content.trim().is_empty() && (!spoiler_mode || content.trim() != "||") {
+        if spoiler_mode {
+            Spoiler::spoilerize(&mut content);
+        }
+        actions.push(Action::SendText(content));
+    }
+
+    if delete {
+        // The attachments of the original message would be lost, only its text is removed
+        actions.push(if has_attachments { Action::Edit(String::new()) } else { Action::Delete });
+    }
+    actions
+}
+
+/// Plans the actions for a message typed by the user, finding its emotes with `lookup`.
+pub fn plan_message<E, F>(content: &str, prefixes: &[&str], spoiler_mode: bool, has_attachments: bool, upload_limit: u64, lookup: F) -> Vec<Action<E>>
+where E: Attachment, F: FnMut(&str, &str, &str) -> Option<E> {
+    let segments = split(content, &emote_regex(prefixes), lookup);
+    plan(segments, content, spoiler_mode, has_attachments, upload_limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Action::*;
+
+    const KNOWN_EMOTES: &[&str] = &[ "kappa", "pog", "pepe/sad" ];
+
+    // Content, spoiler mode, original message has attachments, expected actions
+    type PlanCase = (&'static str, bool, bool, Vec<Action<String>>);
+    // Prefixes, content, expected actions
+    type PrefixCase = (Vec<&'static str>, &'static str, Vec<Action<String>>);
+    // Content, upload limit, expected actions
+    type SizeCase = (&'static str, u64, Vec<Action<String>>);
+
+    /// The emotes are their typed text, one byte per character.
+    impl Attachment for String {
+        fn size(&self) -> u64 {
+            self.len() as u64
+        }
+    }
+
+    /// Finds the emotes of `KNOWN_EMOTES`, described as the prefix, names and modifiers that were typed.
+    fn lookup(prefix: &str, emote: &str, modifiers: &str) -> Option<String> {
+        if emote.split('+').all(|name| KNOWN_EMOTES.contains(&name)) {
+            Some(format!("{}{}{}", prefix, emote, modifiers))
+        } else {
+            None
+        }
+    }
+
+    fn files(emote: &str, content: &str) -> Action<String> {
+        batch(&[ emote ], content)
+    }
+
+    fn batch(emotes: &[&str], content: &str) -> Action<String> {
+        SendFiles(emotes.iter().map(|emote| (*emote).to_owned()).collect(), content.to_owned())
+    }
+
+    fn edit(content: &str) -> Action<String> {
+        Edit(content.to_owned())
+    }
+
+    fn text(content: &str) -> Action<String> {
+        SendText(content.to_owned())
+    }
+
+    #[test]
+    fn plans() {
+        let cases: Vec<PlanCase> = vec![
+            // No emotes
+            ("hello", false, false, vec![]),
+            ("", false, false, vec![]),
+            ("a>kappa", false, false, vec![]), // Not after whitespace
+            (">nope", false, false, vec![]),
+            ("hi >nope there", false, false, vec![]),
+            // A single emote
+            (">kappa", false, false, vec![ files(">kappa", ""), Delete ]),
+            ("  >kappa  ", false, false, vec![ files(">kappa", ""), Delete ]),
+            ("hi >kappa", false, false, vec![ edit("hi "), files(">kappa", "") ]),
+            (">kappa there", false, false, vec![ files(">kappa", ""), text(" there"), Delete ]),
+            ("hi >kappa there", false, false, vec![ edit("hi "), files(">kappa", ""), text(" there") ]),
+            // Several emotes
+            (">kappa >pog", false, false, vec![ batch(&[ ">kappa", ">pog" ], ""), Delete ]),
+            ("hi >kappa >pog >kappa", false, false, vec![ edit("hi "), batch(&[ ">kappa", ">pog", ">kappa" ], "") ]),
+            (">kappa\n>pog", false, false, vec![ batch(&[ ">kappa", ">pog" ], ""), Delete ]),
+            (">kappa>pog", false, false, vec![ files(">kappa", ""), text(">pog"), Delete ]),
+            ("hi >kappa there >pog", false, false, vec![ edit("hi "), files(">kappa", ""), files(">pog", " there ") ]),
+            ("hi >kappa there >pog >kappa", false, false, vec![ edit("hi "), files(">kappa", ""), batch(&[ ">pog", ">kappa" ], " there ") ]),
+            (">kappa >pog there >kappa", false, false, vec![ batch(&[ ">kappa", ">pog" ], ""), batch(&[ ">kappa" ], " there "), Delete ]),
+            (">kappa hi >pog", false, false, vec![ files(">kappa", ""), files(">pog", " hi "), Delete ]),
+            (">kappa >nope", false, false, vec![ files(">kappa", ""), text(" >nope"), Delete ]),
+            (">kappa >nope >pog", false, false, vec![ files(">kappa", ""), files(">pog", " >nope "), Delete ]),
+            (">nope >kappa", false, false, vec![ edit(">nope "), files(">kappa", "") ]),
+            // Packs, combinations and modifiers
+            (">pepe/sad", false, false, vec![ files(">pepe/sad", ""), Delete ]),
+            (">kappa+pog", false, false, vec![ files(">kappa+pog", ""), Delete ]),
+            (">kappa+nope", false, false, vec![]),
+            (">kappa:flip:2x", false, false, vec![ files(">kappa:flip:2x", ""), Delete ]),
+            (">kappa:unknown", false, false, vec![ files(">kappa", ""), text(":unknown"), Delete ]),
+            // The attachments of the original message are kept
+            (">kappa", false, true, vec![ files(">kappa", ""), edit("") ]),
+            ("hi >kappa", false, true, vec![ edit("hi "), files(">kappa", "") ]),
+            // Spoiler mode, the spoiler stage already wrapped the message in pipes
+            ("|| >kappa ||", true, false, vec![ files(">kappa", ""), Delete ]),
+            ("|| hi >kappa ||", true, false, vec![ edit("|| hi ||"), files(">kappa", "") ]),
+            ("|| >kappa there ||", true, false, vec![ files(">kappa", ""), text("|| there ||"), Delete ]),
+            ("|| hi >kappa there >pog ||", true, false, vec![ edit("|| hi ||"), files(">kappa", ""), files(">pog", "|| there ||") ]),
+            ("|| >kappa >pog ||", true, false, vec![ batch(&[ ">kappa", ">pog" ], ""), Delete ]),
+            ("|| >kappa >pog there ||", true, false, vec![ batch(&[ ">kappa", ">pog" ], ""), text("|| there ||"), Delete ]),
+            ("|| hi >kappa there >pog >kappa ||", true, false, vec![ edit("|| hi ||"), files(">kappa", ""), batch(&[ ">pog", ">kappa" ], "|| there ||") ]),
+            ("|| >kappa ||", true, true, vec![ files(">kappa", ""), edit("") ]),
+        ];
+
+        for (content, spoiler_mode, has_attachments, expected) in cases {
+            let actions = plan_message(content, &[">"], spoiler_mode, has_attachments, u64::MAX, lookup);
+            assert_eq!(actions, expected, "content: {:?}, spoiler mode: {}, attachments: {}", content, spoiler_mode, has_attachments);
+        }
+    }
+
+    #[test]
+    fn prefixes() {
+        let cases: Vec<PrefixCase> = vec![
+            (vec![ ">", ">>" ], ">>kappa", vec![ files(">>kappa", ""), Delete ]),
+            (vec![ ">>", ">" ], ">kappa >>pog", vec![ batch(&[ ">kappa", ">>pog" ], ""), Delete ]),
+            (vec![ "%" ], ">kappa", vec![]),
+            (vec![ "." ], "a.kappa .kappa", vec![ edit("a.kappa "), files(".kappa", "") ]), // Prefixes are not regexes
+            (vec![ "" ], "kappa", vec![ files("kappa", ""), Delete ]),
+        ];
+
+        for (prefixes, content, expected) in cases {
+            let actions = plan_message(content, &prefixes, false, false, u64::MAX, lookup);
+            assert_eq!(actions, expected, "prefixes: {:?}, content: {:?}", prefixes, content);
+        }
+    }
+
+    #[test]
+    fn attachment_limit() {
+        let content = vec![ ">kappa"; MAX_ATTACHMENTS * 2 + 1 ].join(" ");
+        let first = vec![ ">kappa"; MAX_ATTACHMENTS ];
+        let expected = vec![ batch(&first, ""), batch(&first, ""), files(">kappa", ""), Delete ];
+        assert_eq!(plan_message(&content, &[">"], false, false, u64::MAX, lookup), expected);
+
+        let content = format!("{} there >pog", content);
+        let actions = plan_message(&content, &[">"], false, false, u64::MAX, lookup);
+        assert_eq!(actions[2], batch(&[ ">kappa" ], ""));
+        assert_eq!(actions[3], files(">pog", " there "));
+    }
+
+    #[test]
+    fn size_limit() {
+        // `>kappa` is 6 bytes and `>pog` 4 bytes
+        let cases: Vec<SizeCase> = vec![
+            (">kappa >pog", 10, vec![ batch(&[ ">kappa", ">pog" ], ""), Delete ]),
+            (">kappa >pog", 9, vec![ files(">kappa", ""), files(">pog", ""), Delete ]),
+            (">kappa >pog >pog", 14, vec![ batch(&[ ">kappa", ">pog", ">pog" ], ""), Delete ]),
+            (">kappa >pog >pog >kappa", 14, vec![ batch(&[ ">kappa", ">pog", ">pog" ], ""), files(">kappa", ""), Delete ]),
+            (">pog >kappa >pog >pog", 10, vec![ batch(&[ ">pog", ">kappa" ], ""), batch(&[ ">pog", ">pog" ], ""), Delete ]),
+            (">kappa >kappa", 5, vec![ files(">kappa", ""), files(">kappa", ""), Delete ]), // Too large on their own, still sent
+            ("hi >kappa >pog", 9, vec![ edit("hi "), files(">kappa", ""), files(">pog", "") ]),
+        ];
+
+        for (content, upload_limit, expected) in cases {
+            let actions = plan_message(content, &[">"], false, false, upload_limit, lookup);
+            assert_eq!(actions, expected, "content: {:?}, upload limit: {}", content, upload_limit);
+        }
+    }
+
+    #[test]
+    fn rewritten_text() {
+        let segments = vec![ Segment::<String>::Text("¯\\_(ツ)_/¯".to_owned()) ];
+        assert_eq!(plan(segments, "$shrug", false, false, u64::MAX), vec![ edit("¯\\_(ツ)_/¯") ]);
+
+        let segments = vec![ Segment::<String>::Text("same".to_owned()) ];
+        assert_eq!(plan(segments, "same", false, false, u64::MAX), vec![]);
+    }
+}
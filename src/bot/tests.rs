@@ -0,0 +1,295 @@
+use super::*;
+use super::channel::{ ChannelAction::{ self, * }, RecordingChannel };
+use crate::{
+    config::{ EmoteCorrection, EmoteSourceBinding, EmotesConfig, StatsConfig },
+    sources::LOCAL_SOURCE,
+    testing::{ self, TempDir },
+};
+
+use std::{
+    path::PathBuf,
+    sync::mpsc,
+};
+
+use serenity::{
+    cache::{ Cache, CacheRwLock },
+    client::bridge::gateway::ShardMessenger,
+    http::Http,
+};
+use typemap::ShareMap;
+use serde_json::json;
+
+const USER_ID: u64 = 1;
+const CHANNEL_ID: u64 = 2;
+const MESSAGE_ID: u64 = 3;
+/// Types of the messages sent by the gateway.
+const REGULAR_TYPE: u8 = 0;
+const REPLY_TYPE: u8 = 19;
+
+/// An assets directory with 8x8 emojis, removed at the end of the test.
+struct Library {
+    directory: TempDir,
+    mngr: Arc<EmoteManager>,
+}
+
+impl Library {
+    fn new(emotes: &[&str]) -> Self {
+        let directory = TempDir::new("library");
+        std::fs::create_dir_all(directory.path().join("emojis")).unwrap();
+        for name in emotes {
+            std::fs::write(directory.path().join("emojis").join(format!("{}.png", name)), testing::png(8, 8)).unwrap();
+        }
+        let mngr = Arc::new(EmoteManager::private(&EmotesConfig::default(), directory.path()).unwrap());
+        Self {
+            directory,
+            mngr,
+        }
+    }
+
+    fn emote_path(&self, name: &str) -> PathBuf {
+        self.directory.path().join("emojis").join(format!("{}.png", name))
+    }
+}
+
+fn bot() -> (Bot, Arc<RecordingChannel>) {
+    bot_with_channel(RecordingChannel::default())
+}
+
+fn bot_with_channel(channel: RecordingChannel) -> (Bot, Arc<RecordingChannel>) {
+    bot_with_private_emotes(channel, None)
+}
+
+fn bot_with_private_emotes(channel: RecordingChannel, private_emotes: Option<Arc<EmoteManager>>) -> (Bot, Arc<RecordingChannel>) {
+    let user = User {
+        active: true,
+        discord_id: USER_ID,
+        command_prefix: "s.".into(),
+        emote_sources: vec![ EmoteSourceBinding::new(">", &[ LOCAL_SOURCE ]) ],
+        emote_correction: EmoteCorrection::Suggest,
+        transformers: transformers::DEFAULT_PIPELINE.iter().map(|name| (*name).to_owned()).collect(),
+        ..User::default()
+    };
+    let channel = Arc::new(channel);
+    (Bot::with_channel(user, private_emotes, channel.clone()), channel)
+}
+
+/// A context that is never connected to Discord, the channel operations go through the `RecordingChannel`.
+/// The emotes are loaded from a library with a `kappa` emote, that must outlive the context.
+fn context() -> (Context, Library) {
+    let stats = StatsConfig {
+        enabled: false,
+        ..Default::default()
+    };
+    let library = Library::new(&[ "kappa" ]);
+
+    let mut data = ShareMap::custom();
+    data.insert::<UsageStats>(Arc::new(UsageStats::new(&stats).unwrap()));
+    data.insert::<UserSettingsKey>(UserSettings::default());
+    data.insert::<EmoteManager>(library.mngr.clone());
+
+    let (tx, _rx) = mpsc::channel();
+    let ctx = Context {
+        data: Arc::new(RwLock::new(data)),
+        shard: ShardMessenger::new(tx),
+        shard_id: 0,
+        http: Arc::new(Http::new_with_token("")),
+        cache: CacheRwLock::from(Arc::new(RwLock::new(Cache::default()))),
+    };
+    (ctx, library)
+}
+
+fn message(content: &str) -> Message {
+    typed_message(content, REGULAR_TYPE)
+}
+
+fn typed_message(content: &str, kind: u8) -> Message {
+    serde_json::from_value(json!({
+        "id": MESSAGE_ID.to_string(),
+        "channel_id": CHANNEL_ID.to_string(),
+        "author": {
+            "id": USER_ID.to_string(),
+            "username": "user",
+            "discriminator": "0001",
+            "avatar": null,
+        },
+        "content": content,
+        "timestamp": "2020-01-01T00:00:00+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "pinned": false,
+        "type": kind,
+    })).unwrap()
+}
+
+fn update_event(content: &str) -> MessageUpdateEvent {
+    serde_json::from_value(json!({
+        "id": MESSAGE_ID.to_string(),
+        "channel_id": CHANNEL_ID.to_string(),
+        "content": content,
+    })).unwrap()
+}
+
+fn enable_spoiler_mode(ctx: &Context) {
+    ctx.data.write().get_mut::<UserSettingsKey>().unwrap().spoiler_mode.insert(CHANNEL_ID);
+}
+
+#[test]
+fn plain_message_is_untouched() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, Some(&mut message("hello")), None).unwrap();
+    assert_eq!(channel.actions(), vec![]);
+}
+
+#[test]
+fn spoiler_command_toggles_spoiler_mode() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+
+    bot.handle_message(ctx.clone(), Some(&mut message("s.spoiler")), None).unwrap();
+    assert!(ctx.data.read().get::<UserSettingsKey>().unwrap().spoiler_mode(CHANNEL_ID));
+    bot.handle_message(ctx.clone(), Some(&mut message("s.sm")), None).unwrap();
+    assert!(!ctx.data.read().get::<UserSettingsKey>().unwrap().spoiler_mode(CHANNEL_ID));
+
+    // The command messages are deleted
+    let delete = Delete { channel_id: CHANNEL_ID, message_id: MESSAGE_ID };
+    assert_eq!(channel.actions(), vec![ delete.clone(), delete ]);
+}
+
+#[test]
+fn spoiler_mode_edits_message() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+    enable_spoiler_mode(&ctx);
+
+    bot.handle_message(ctx.clone(), Some(&mut message("hello")), None).unwrap();
+    bot.handle_message(ctx, None, Some(&update_event("|| hello ||"))).unwrap();
+    assert_eq!(channel.actions(), vec![
+        Edit { channel_id: CHANNEL_ID, message_id: MESSAGE_ID, content: "|| hello ||".into() },
+    ]);
+}
+
+#[test]
+fn add_command_without_name_shows_usage() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, Some(&mut message("s.add")), None).unwrap();
+    assert_eq!(channel.actions(), vec![
+        SendMessage { channel_id: CHANNEL_ID, content: "Usage: `s.add <name> [category]`".into(), reply: None },
+        Delete { channel_id: CHANNEL_ID, message_id: MESSAGE_ID },
+    ]);
+}
+
+fn files(names: &[&str], content: &str) -> ChannelAction {
+    SendFiles { channel_id: CHANNEL_ID, files: names.iter().map(|name| (*name).to_owned()).collect(), content: content.into(), reply: None }
+}
+
+#[test]
+fn emote_replaces_message() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, Some(&mut message(">kappa")), None).unwrap();
+    assert_eq!(channel.actions(), vec![
+        files(&[ "kappa.png" ], ""),
+        Delete { channel_id: CHANNEL_ID, message_id: MESSAGE_ID },
+    ]);
+}
+
+#[test]
+fn text_before_emote_is_kept() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, Some(&mut message("look >kappa >kappa")), None).unwrap();
+    assert_eq!(channel.actions(), vec![
+        Edit { channel_id: CHANNEL_ID, message_id: MESSAGE_ID, content: "look ".into() },
+        files(&[ "kappa.png", "kappa.png" ], ""),
+    ]);
+}
+
+#[test]
+fn spoiler_mode_spoilers_text_around_emote() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+    enable_spoiler_mode(&ctx);
+
+    // The message is spoilered first, the emotes are sent when the edit comes back as an update event
+    bot.handle_message(ctx.clone(), Some(&mut message("look >kappa")), None).unwrap();
+    bot.handle_message(ctx, None, Some(&update_event("|| look >kappa ||"))).unwrap();
+    assert_eq!(channel.actions(), vec![
+        Edit { channel_id: CHANNEL_ID, message_id: MESSAGE_ID, content: "|| look >kappa ||".into() },
+        Edit { channel_id: CHANNEL_ID, message_id: MESSAGE_ID, content: "|| look ||".into() },
+        files(&[ "kappa.png" ], ""),
+    ]);
+}
+
+#[test]
+fn unknown_emote_is_suggested() {
+    let (bot, channel) = bot();
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, Some(&mut message("hi >kapa")), None).unwrap();
+    assert_eq!(channel.actions(), vec![
+        SendMessage { channel_id: CHANNEL_ID, content: "Unknown emote `>kapa`, did you mean `>kappa`?".into(), reply: None },
+    ]);
+}
+
+fn replying_channel() -> RecordingChannel {
+    let mut channel = RecordingChannel::default();
+    channel.reply = Some(Reply { channel_id: CHANNEL_ID, message_id: MESSAGE_ID + 1, guild_id: None, mention: true });
+    channel
+}
+
+/// The first message sent in place of a deleted reply replies to the same message, the next ones do not.
+fn assert_reply_on_first_message(actions: Vec<ChannelAction>, reply: &Option<Reply>) {
+    assert_eq!(actions, vec![
+        SendFiles { channel_id: CHANNEL_ID, files: vec![ "kappa.png".into() ], content: "".into(), reply: reply.clone() },
+        files(&[ "kappa.png" ], " hi "),
+        SendMessage { channel_id: CHANNEL_ID, content: " there".into(), reply: None },
+        Delete { channel_id: CHANNEL_ID, message_id: MESSAGE_ID },
+    ]);
+}
+
+#[test]
+fn deleted_reply_is_replied_to() {
+    let (bot, channel) = bot_with_channel(replying_channel());
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, Some(&mut typed_message(">kappa hi >kappa there", REPLY_TYPE)), None).unwrap();
+    assert_reply_on_first_message(channel.actions(), &channel.reply);
+}
+
+#[test]
+fn deleted_reply_update_is_replied_to() {
+    // The gateway does not always send the type of updated messages, they are looked up
+    let (bot, channel) = bot_with_channel(replying_channel());
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, None, Some(&update_event(">kappa hi >kappa there"))).unwrap();
+    assert_reply_on_first_message(channel.actions(), &channel.reply);
+}
+
+#[test]
+fn regular_message_is_not_looked_up() {
+    let (bot, channel) = bot_with_channel(replying_channel());
+    let (ctx, _library) = context();
+    bot.handle_message(ctx, Some(&mut message(">kappa hi >kappa there")), None).unwrap();
+    assert_reply_on_first_message(channel.actions(), &None);
+}
+
+#[test]
+fn private_emote_is_loaded_by_its_library() {
+    let private = Library::new(&[ "secret" ]);
+    let (bot, channel) = bot_with_private_emotes(RecordingChannel::default(), Some(private.mngr.clone()));
+    let (ctx, _library) = context();
+    bot.handle_message(ctx.clone(), Some(&mut message(">secret")), None).unwrap();
+
+    // The watcher of the private emotes clears their cache when the file is replaced
+    let replacement = testing::png(16, 16);
+    testing::replace_file(&private.emote_path("secret"), &replacement);
+    private.mngr.reload().unwrap();
+    bot.handle_message(ctx, Some(&mut message(">secret")), None).unwrap();
+
+    assert_eq!(channel.payloads(), vec![ testing::png(8, 8), replacement ]);
+}
@@ -1,4 +1,9 @@
-use std::collections::HashSet;
+use std::{
+    path::PathBuf,
+    collections::HashSet,
+};
+
+use crate::config::{ EmoteCorrection, EmoteSourceBinding, TextEmote };
 
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct User {
@@ -9,6 +14,12 @@ pub struct User {
     pub emote_prefix: String,
     pub twitch_emote_prefix: String,
     pub text_emote_prefix: String,
+    pub emote_correction: EmoteCorrection,
+    pub text_emotes: Vec<TextEmote>,
+    pub emote_sources: Vec<EmoteSourceBinding>, // Sources to search for each emote prefix
+    pub transformers: Vec<String>, // Names of the stages the messages go through, in order
+    pub private_emotes: Option<PathBuf>, // Assets directory of emotes only this user can send, layered over the shared library
+    pub www_key: Option<String>, // Secret authenticating the user to the web server
 }
 
 pub struct UserSettingsKey;
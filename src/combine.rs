@@ -0,0 +1,172 @@
+use std::io::Cursor;
+
+use crate::{
+    media::{ self, MediaFormat },
+    Error, ErrorKind, Result,
+};
+
+use image::{
+    Delay, Frame, RgbaImage, DynamicImage, ImageOutputFormat, AnimationDecoder,
+    imageops::{ self, FilterType },
+    codecs::gif::{ GifDecoder, Repeat },
+};
+
+/// Number of emotes per row, more emotes wrap into a grid.
+const MAX_COLUMNS: usize = 4;
+/// Height every emote is scaled to, unless they are all smaller.
+const MAX_HEIGHT: u32 = 128;
+/// Limits for the combined animation, to keep the GIF small.
+const MAX_DURATION_MS: f64 = 10_000.0;
+const MAX_FRAMES: usize = 200;
+
+/// The frames of an emote with the time at which each one ends, in milliseconds.
+struct Animation {
+    frames: Vec<RgbaImage>,
+    ends: Vec<f64>,
+}
+
+impl Animation {
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if MediaFormat::sniff(bytes) != MediaFormat::Gif {
+            let image = image::load_from_memory(bytes)?.to_rgba8();
+            return Ok(Self {
+                frames: vec![image],
+                ends: vec![0.0],
+            });
+        }
+
+        let mut animation = Self {
+            frames: Vec::new(),
+            ends: Vec::new(),
+        };
+        let mut time = 0.0;
+        for frame in GifDecoder::new(Cursor::new(bytes))?.into_frames() {
+            let frame = frame?;
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            time += numerator as f64 / denominator.max(1) as f64;
+            animation.frames.push(frame.into_buffer());
+            animation.ends.push(time);
+        }
+        Ok(animation)
+    }
+
+    fn duration(&self) -> f64 {
+        self.ends.last().cloned().unwrap_or(0.0)
+    }
+
+    fn shortest_delay(&self) -> Option<f64> {
+        let mut start = 0.0;
+        let mut shortest: Option<f64> = None;
+        for end in self.ends.iter() {
+            let delay = end - start;
+            if delay > 0.0 {
+                shortest = Some(shortest.map_or(delay, |shortest| shortest.min(delay)));
+            }
+            start = *end;
+        }
+        shortest
+    }
+
+    /// Returns the frame shown at `time`, looping the animation.
+    fn frame_at(&self, time: f64) -> &RgbaImage {
+        let duration = self.duration();
+        if self.frames.len() == 1 || duration <= 0.0 {
+            return &self.frames[0];
+        }
+
+        let time = time % duration;
+        let idx = self.ends.iter().position(|end| time < *end).unwrap_or(self.frames.len() - 1);
+        &self.frames[idx]
+    }
+
+    fn resize(&mut self, height: u32) {
+        for frame in self.frames.iter_mut() {
+            if frame.height() != height {
+                let width = (frame.width() * height / frame.height().max(1)).max(1);
+                *frame = imageops::resize(frame, width, height, FilterType::CatmullRom);
+            }
+        }
+    }
+}
+
+/// Composes emotes side by side into a single image, wrapping them in a grid past `MAX_COLUMNS`.
+/// Animated emotes play simultaneously from the start and each one loops on its own.
+/// The result is scaled down if needed to stay under `max_size` bytes.
+pub fn combine(emotes: &[&[u8]], max_size: u64) -> Result<(Vec<u8>, MediaFormat)> {
+    let mut animations = emotes.iter().map(|bytes| Animation::decode(bytes)).collect::<Result<Vec<_>>>()?;
+
+    let height = animations
+                    .iter()
+                    .map(|animation| animation.frames[0].height())
+                    .max()
+                    .unwrap_or(1)
+                    .min(MAX_HEIGHT);
+    for animation in animations.iter_mut() {
+        animation.resize(height);
+    }
+
+    let rows = animations.chunks(MAX_COLUMNS).collect::<Vec<_>>();
+    let width = rows
+                    .iter()
+                    .map(|row| row.iter().map(|animation| animation.frames[0].width()).sum::<u32>())
+                    .max()
+                    .unwrap_or(1);
+    let render = |time: f64| {
+        let mut canvas = RgbaImage::new(width, height * rows.len() as u32);
+        for (row_idx, row) in rows.iter().enumerate() {
+            let mut x = 0;
+            for animation in row.iter() {
+                let frame = animation.frame_at(time);
+                imageops::overlay(&mut canvas, frame, x, row_idx as u32 * height);
+                x += frame.width();
+            }
+        }
+        canvas
+    };
+
+    let duration = animations.iter().map(Animation::duration).fold(0.0, f64::max).min(MAX_DURATION_MS);
+    if duration <= 0.0 {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(render(0.0)).write_to(&mut png, ImageOutputFormat::Png)?;
+        if png.len() as u64 > max_size {
+            return Err(Error::with_message(ErrorKind::Image, format!("the image does not fit in the upload limit of {} bytes", max_size)));
+        }
+        return Ok((png, MediaFormat::Png));
+    }
+
+    let step = animations
+                    .iter()
+                    .filter_map(Animation::shortest_delay)
+                    .fold(duration, f64::min)
+                    .max(media::MIN_FRAME_DELAY_MS)
+                    .max(duration / MAX_FRAMES as f64);
+    let n_frames = (duration / step).ceil() as usize;
+    let delay = Delay::from_numer_denom_ms(step.round() as u32, 1);
+    let frames = (0..n_frames).map(|idx| Frame::from_parts(render(idx as f64 * step), 0, 0, delay)).collect();
+
+    Ok((media::encode_gif_within(frames, Repeat::Infinite, max_size)?, MediaFormat::Gif))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height)).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        png
+    }
+
+    #[test]
+    fn static_size_limit() {
+        let (small, tall) = (png(8, 8), png(4, 16));
+        let (combined, format) = combine(&[ &small, &tall ], u64::MAX).unwrap();
+        assert_eq!(format, MediaFormat::Png);
+        let image = image::load_from_memory(&combined).unwrap();
+        assert_eq!((image.width(), image.height()), (16 + 4, 16)); // Scaled to the tallest emote
+
+        assert!(combine(&[ &small, &tall ], combined.len() as u64).is_ok());
+        assert!(combine(&[ &small, &tall ], combined.len() as u64 - 1).is_err());
+    }
+}
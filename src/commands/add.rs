@@ -0,0 +1,94 @@
+use super::*;
+
+use crate::{
+    EmoteManager,
+    bot::discord_api,
+    error::{ Error, ErrorKind },
+};
+
+pub struct Add {
+    names: Vec<&'static str>,
+}
+
+impl Default for Add {
+    fn default() -> Self {
+        Self {
+            names: vec![ "add" ],
+        }
+    }
+}
+
+impl Add {
+    pub fn boxed() -> Box<Self> {
+        Box::new(Self::default())
+    }
+
+    /// The URL and size of the first attachment of the message, or else of the message it replies to.
+    fn attachment(bot: &Bot, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<Option<(String, u64)>> {
+        let attachment = if let Some(msg) = msg {
+            msg.attachments.first().map(|attachment| (attachment.url.clone(), attachment.size))
+        } else if let Some(event) = event {
+            event.attachments.as_ref().and_then(|attachments| attachments.first()).map(|attachment| (attachment.url.clone(), attachment.size))
+        } else {
+            None
+        };
+        if attachment.is_some() {
+            return Ok(attachment);
+        }
+
+        let replied_to = discord_api::referenced_message(&bot.user.token, bot.channel_id(msg, event), bot.message_id(msg, event))?;
+        Ok(replied_to.and_then(|message| message.attachments.into_iter().next()).map(|attachment| (attachment.url, attachment.size)))
+    }
+
+    fn add(bot: &Bot, mngr: &EmoteManager, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, name: &str, category: Option<&str>) -> Result<String> {
+        let (url, size) = Self::attachment(bot, msg, event)?
+                            .ok_or_else(|| Error::with_message(ErrorKind::EditLibrary, "attach a file or reply to a message with one".into()))?;
+        if size > mngr.upload_limit() {
+            return Err(Error::with_message(
+                ErrorKind::EditLibrary,
+                format!("the file is {} bytes, more than the upload limit of {} bytes", size, mngr.upload_limit()),
+            ));
+        }
+
+        let mut res = reqwest::blocking::get(&url)?.error_for_status()?;
+        let content_type = res.headers()
+                                .get(reqwest::header::CONTENT_TYPE)
+                                .and_then(|val| val.to_str().ok())
+                                .map(String::from);
+        let mut bytes: Vec<u8> = Vec::new();
+        res.copy_to(&mut bytes)?;
+
+        let emote = mngr.add_emote(name, category, bytes, content_type.as_deref())?;
+        Ok(emote.qualified_name())
+    }
+}
+
+impl Command for Add {
+    fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    fn handle_message(&self, bot: &Bot, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<()> {
+        // s.add <name> [category]
+        let content = bot.message_content(msg, event);
+        let mut args = content.split_whitespace().skip(1);
+        let name = match args.next() {
+            Some(name) => name,
+            None => {
+                bot.send_message(ctx, &msg, event, &format!("Usage: `{}add <name> [category]`", bot.user.command_prefix))?;
+                return Ok(());
+            },
+        };
+        let category = args.next();
+
+        // Cloned so that the client data is not locked during the download
+        let mngr = ctx.data.read().get::<EmoteManager>().cloned().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+        let reply = match Self::add(bot, &mngr, msg, event, name, category) {
+            Ok(name) => format!("Added emote `{}{}`.", bot.user.emote_prefix, name),
+            Err(err) => format!("Could not add emote `{}`: {}", name, err),
+        };
+
+        bot.send_message(ctx, &msg, event, &reply)?;
+        Ok(())
+    }
+}
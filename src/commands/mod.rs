@@ -1,4 +1,8 @@
-use crate::{ bot::Bot, error::Result };
+use crate::{
+    bot::Bot,
+    config::Config,
+    error::{ Error, ErrorKind, Result },
+};
 
 use serenity::{
     prelude::*,
@@ -12,7 +16,26 @@ pub use palette::Palette;
 pub mod spoiler;
 pub use spoiler::Spoiler;
 
+pub mod stats;
+pub use stats::Stats;
+
+pub mod add;
+pub use add::Add;
+
+pub mod rename;
+pub use rename::Rename;
+
+pub mod remove;
+pub use remove::Remove;
+
 pub trait Command {
     fn names(&self) -> &[&'static str];
     fn handle_message(&self, bot: &Bot, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<()>;
 }
+
+/// Whether the user is listed in the `editors` of the emotes configuration.
+fn is_library_editor(bot: &Bot, ctx: &Context) -> Result<bool> {
+    let data = ctx.data.read();
+    let config = data.get::<Config>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+    Ok(config.emotes.editors.contains(&bot.user.discord_id))
+}
@@ -32,7 +32,7 @@ impl Command for Palette {
         let data = ctx.data.read();
         let config = data.get::<Config>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
         let url = format!("{}/palette", config.www.base_url);
-        bot.send_message(ctx, &msg, event, |m| m.content(&url))?;
+        bot.send_message(ctx, &msg, event, &url)?;
         Ok(())
     }
 }
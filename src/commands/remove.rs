@@ -0,0 +1,50 @@
+use super::*;
+
+use crate::{
+    EmoteManager,
+    error::{ Error, ErrorKind },
+};
+
+pub struct Remove {
+    names: Vec<&'static str>,
+}
+
+impl Default for Remove {
+    fn default() -> Self {
+        Self {
+            names: vec![ "rm", "remove" ],
+        }
+    }
+}
+
+impl Remove {
+    pub fn boxed() -> Box<Self> {
+        Box::new(Self::default())
+    }
+}
+
+impl Command for Remove {
+    fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    fn handle_message(&self, bot: &Bot, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<()> {
+        // s.rm <name>
+        let content = bot.message_content(msg, event);
+        let args = content.split_whitespace().skip(1).collect::<Vec<_>>();
+        let reply = if !is_library_editor(bot, ctx)? {
+            "You are not allowed to edit the emote library.".to_owned()
+        } else if let [name] = args.as_slice() {
+            let mngr = ctx.data.read().get::<EmoteManager>().cloned().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+            match mngr.remove_emote(name) {
+                Ok(trash_path) => format!("Moved emote `{}{}` to the trash ({}).", bot.user.emote_prefix, name, trash_path.display()),
+                Err(err) => format!("Could not remove emote `{}`: {}", name, err),
+            }
+        } else {
+            format!("Usage: `{}rm <name>`", bot.user.command_prefix)
+        };
+
+        bot.send_message(ctx, &msg, event, &reply)?;
+        Ok(())
+    }
+}
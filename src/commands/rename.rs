@@ -0,0 +1,50 @@
+use super::*;
+
+use crate::{
+    EmoteManager,
+    error::{ Error, ErrorKind },
+};
+
+pub struct Rename {
+    names: Vec<&'static str>,
+}
+
+impl Default for Rename {
+    fn default() -> Self {
+        Self {
+            names: vec![ "rename", "mv" ],
+        }
+    }
+}
+
+impl Rename {
+    pub fn boxed() -> Box<Self> {
+        Box::new(Self::default())
+    }
+}
+
+impl Command for Rename {
+    fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    fn handle_message(&self, bot: &Bot, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<()> {
+        // s.rename <name> <new name>
+        let content = bot.message_content(msg, event);
+        let args = content.split_whitespace().skip(1).collect::<Vec<_>>();
+        let reply = if !is_library_editor(bot, ctx)? {
+            "You are not allowed to edit the emote library.".to_owned()
+        } else if let [name, new_name] = args.as_slice() {
+            let mngr = ctx.data.read().get::<EmoteManager>().cloned().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+            match mngr.rename_emote(name, new_name) {
+                Ok(emote) => format!("Renamed emote `{}{}` to `{}{}`.", bot.user.emote_prefix, name, bot.user.emote_prefix, emote.qualified_name()),
+                Err(err) => format!("Could not rename emote `{}`: {}", name, err),
+            }
+        } else {
+            format!("Usage: `{}rename <name> <new name>`", bot.user.command_prefix)
+        };
+
+        bot.send_message(ctx, &msg, event, &reply)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use super::*;
+
+use crate::{
+    EmoteManager,
+    stats::UsageStats,
+    error::{ Error, ErrorKind },
+};
+
+/// Period covered when the command is given no number of days.
+const DEFAULT_DAYS: u32 = 30;
+const TOP_SIZE: usize = 10;
+
+pub struct Stats {
+    names: Vec<&'static str>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            names: vec![ "stats", "top" ],
+        }
+    }
+}
+
+impl Stats {
+    pub fn boxed() -> Box<Self> {
+        Box::new(Self::default())
+    }
+}
+
+impl Command for Stats {
+    fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    fn handle_message(&self, bot: &Bot, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>) -> Result<()> {
+        // s.stats [days]
+        let days = bot.message_content(msg, event)
+                        .split_whitespace()
+                        .nth(1)
+                        .and_then(|days| days.parse().ok())
+                        .unwrap_or(DEFAULT_DAYS);
+
+        let data = ctx.data.read();
+        let stats = data.get::<UsageStats>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+        let mngr = data.get::<EmoteManager>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+        let top = stats.top(Some(days), Some(bot.user.discord_id))?;
+
+        let mut content = if top.is_empty() {
+            format!("No emotes used in the last {} days.", days)
+        } else {
+            let lines = top
+                            .iter()
+                            .take(TOP_SIZE)
+                            .enumerate()
+                            .map(|(idx, count)| format!("{}. `{}` ({})", idx + 1, count.name, count.count))
+                            .collect::<Vec<_>>();
+            format!("**Most used emotes in the last {} days**\n{}", days, lines.join("\n"))
+        };
+
+        let unused = mngr.emotes()?
+                        .iter()
+                        .filter(|emote| {
+                            let name = emote.qualified_name();
+                            !top.iter().any(|count| count.name == name)
+                        })
+                        .count();
+        if unused > 0 {
+            content.push_str(&format!("\n{} emote{} of the library not used.", unused, if unused == 1 { "" } else { "s" }));
+        }
+
+        bot.send_message(ctx, &msg, event, &content)?;
+        Ok(())
+    }
+}
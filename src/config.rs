@@ -1,10 +1,13 @@
 use std::{
     sync::Arc,
+    path::{ Path, PathBuf },
     collections::HashMap,
 };
 
 use crate::{
     bot::User,
+    sources,
+    transformers,
     error::{ Error, ErrorKind, Result },
 };
 
@@ -15,6 +18,16 @@ use serde::{ Serialize, Deserialize };
 pub struct Config {
     pub logging: LoggingConfig,
     pub www: WwwConfig,
+    #[serde(default)]
+    pub emotes: EmotesConfig,
+    #[serde(default)]
+    pub twitch_cache: TwitchCacheConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub sources: HashMap<String, SourceConfig>,
+    #[serde(default)]
+    pub text_emotes: Vec<TextEmote>,
     pub default_user: UserConfig,
     pub users: HashMap<String, UserConfig>,
 }
@@ -42,56 +55,97 @@ impl Config {
         self.users
                 .iter()
                 .map(|pair| pair.1)
-                .map(|user_config| User {
-                    active: match user_config.active {
-                        Some(val) => val,
-                        None => match self.default_user.active {
+                .map(|user_config| {
+                    let mut user = User {
+                        active: match user_config.active {
                             Some(val) => val,
-                            None => true,
-                        }
-                    },
-                    discord_id: match user_config.discord_id {
-                        Some(val) => val,
-                        None => match self.default_user.discord_id {
+                            None => match self.default_user.active {
+                                Some(val) => val,
+                                None => true,
+                            }
+                        },
+                        discord_id: match user_config.discord_id {
                             Some(val) => val,
-                            None => 0,
-                        }
-                    },
-                    token: match &user_config.token {
-                        Some(val) => val.clone(),
-                        None => match &self.default_user.token {
+                            None => match self.default_user.discord_id {
+                                Some(val) => val,
+                                None => 0,
+                            }
+                        },
+                        token: match &user_config.token {
                             Some(val) => val.clone(),
-                            None => String::new(),
-                        }
-                    },
-                    command_prefix: match &user_config.command_prefix {
-                        Some(val) => val.clone(),
-                        None => match &self.default_user.command_prefix {
+                            None => match &self.default_user.token {
+                                Some(val) => val.clone(),
+                                None => String::new(),
+                            }
+                        },
+                        command_prefix: match &user_config.command_prefix {
                             Some(val) => val.clone(),
-                            None => "s.".to_owned(),
-                        }
-                    },
-                    emote_prefix: match &user_config.emote_prefix {
-                        Some(val) => val.clone(),
-                        None => match &self.default_user.emote_prefix {
+                            None => match &self.default_user.command_prefix {
+                                Some(val) => val.clone(),
+                                None => "s.".to_owned(),
+                            }
+                        },
+                        emote_prefix: match &user_config.emote_prefix {
                             Some(val) => val.clone(),
-                            None => ">".to_owned(),
-                        }
-                    },
-                    twitch_emote_prefix: match &user_config.twitch_emote_prefix {
-                        Some(val) => val.clone(),
-                        None => match &self.default_user.twitch_emote_prefix {
+                            None => match &self.default_user.emote_prefix {
+                                Some(val) => val.clone(),
+                                None => ">".to_owned(),
+                            }
+                        },
+                        twitch_emote_prefix: match &user_config.twitch_emote_prefix {
                             Some(val) => val.clone(),
-                            None => "%".to_owned(),
-                        }
-                    },
-                    text_emote_prefix: match &user_config.text_emote_prefix {
-                        Some(val) => val.clone(),
-                        None => match &self.default_user.text_emote_prefix {
+                            None => match &self.default_user.twitch_emote_prefix {
+                                Some(val) => val.clone(),
+                                None => "%".to_owned(),
+                            }
+                        },
+                        text_emote_prefix: match &user_config.text_emote_prefix {
                             Some(val) => val.clone(),
-                            None => "$".to_owned(),
-                        }
-                    },
+                            None => match &self.default_user.text_emote_prefix {
+                                Some(val) => val.clone(),
+                                None => "$".to_owned(),
+                            }
+                        },
+                        emote_correction: match user_config.emote_correction {
+                            Some(val) => val,
+                            None => match self.default_user.emote_correction {
+                                Some(val) => val,
+                                None => EmoteCorrection::Off,
+                            }
+                        },
+                        text_emotes: match &user_config.text_emotes {
+                            Some(val) => val.clone(),
+                            None => match &self.default_user.text_emotes {
+                                Some(val) => val.clone(),
+                                None => Vec::new(),
+                            }
+                        },
+                        emote_sources: match &user_config.emote_sources {
+                            Some(val) => val.clone(),
+                            None => match &self.default_user.emote_sources {
+                                Some(val) => val.clone(),
+                                None => Vec::new(),
+                            }
+                        },
+                        transformers: match &user_config.transformers {
+                            Some(val) => val.clone(),
+                            None => match &self.default_user.transformers {
+                                Some(val) => val.clone(),
+                                None => transformers::DEFAULT_PIPELINE.iter().map(|name| name.to_string()).collect(),
+                            }
+                        },
+                        // Not inherited from the default user, they belong to a single user
+                        private_emotes: user_config.private_emotes.clone(),
+                        www_key: user_config.www_key.clone(),
+                    };
+
+                    if user.emote_sources.is_empty() {
+                        user.emote_sources = vec![
+                            EmoteSourceBinding::new(&user.emote_prefix, &[sources::LOCAL_SOURCE]),
+                            EmoteSourceBinding::new(&user.twitch_emote_prefix, &[sources::TWITCH_SOURCE]),
+                        ];
+                    }
+                    user
                 })
                 .collect()
     }
@@ -122,6 +176,128 @@ pub struct WwwConfig {
     pub twitch_emotes_manager_port: u16,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmotesConfig {
+    pub directory: PathBuf,
+    pub categories: Vec<CategoryConfig>,
+    pub conflict_policy: ConflictPolicy,
+    pub mmap: bool,
+    pub cache_size: u64, // Maximum size in bytes of the emote payloads kept in memory, 0 to disable the cache
+    pub modified_cache_size: u64, // Same for the emotes rendered with modifiers (:2x, :flip...)
+    pub upload_limit: u64, // Maximum size in bytes of a file sent to Discord
+    pub trash_directory: PathBuf, // Where removed emotes are moved, relative to `directory`
+    pub editors: Vec<u64>, // Discord ids of the users allowed to rename and remove emotes
+}
+
+impl Default for EmotesConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("assets"),
+            categories: vec![
+                CategoryConfig::new("emojis", "Emoji", &["png", "jpg", "jpeg", "gif", "webp"]),
+                CategoryConfig::new("gifs", "GIF", &["gif", "png", "webp"]),
+                CategoryConfig::new("sounds", "Sound", &["mp3", "ogg", "wav"]),
+            ],
+            conflict_policy: ConflictPolicy::default(),
+            mmap: false,
+            cache_size: 16 * 1024 * 1024,
+            modified_cache_size: 16 * 1024 * 1024,
+            upload_limit: 8 * 1024 * 1024,
+            trash_directory: PathBuf::from(".trash"),
+            editors: Vec::new(),
+        }
+    }
+}
+
+/// A subdirectory of the assets directory holding one kind of emotes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CategoryConfig {
+    pub directory: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub extensions: Vec<String>, // Accepted file extensions, any file is accepted if empty
+    #[serde(default = "CategoryConfig::default_palette")]
+    pub palette: bool, // Whether the category is listed in the web palette
+}
+
+impl CategoryConfig {
+    pub fn new(directory: &str, display_name: &str, extensions: &[&str]) -> Self {
+        Self {
+            directory: directory.to_owned(),
+            display_name: display_name.to_owned(),
+            extensions: extensions.iter().map(|extension| extension.to_string()).collect(),
+            palette: true,
+        }
+    }
+
+    fn default_palette() -> bool {
+        true
+    }
+
+    pub fn accepts(&self, path: &Path) -> bool {
+        if self.extensions.is_empty() {
+            return true;
+        }
+
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        self.extensions.iter().any(|accepted| accepted.to_lowercase() == extension)
+    }
+}
+
+/// What to do with emotes that use a name or an alias that another emote already has.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    Priority, // Keep the emote of the category listed first, or the first one by path in the same category
+    Error, // Refuse to load the library
+    Suffix, // Load the other emotes as `name_2`, `name_3`...
+}
+
+impl Default for ConflictPolicy {
+    fn default() -> Self {
+        ConflictPolicy::Priority
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TwitchCacheConfig {
+    pub enabled: bool,
+    pub directory: PathBuf,
+    pub ttl: u64, // Seconds after which a cached emote is fetched again, the cached one is still used if the manager is unreachable
+    pub max_size: u64, // Bytes
+}
+
+impl Default for TwitchCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: PathBuf::from("cache/twitch"),
+            ttl: 7 * 24 * 60 * 60,
+            max_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatsConfig {
+    pub enabled: bool,
+    pub file: PathBuf,
+    pub flush_interval: u64, // Seconds between two writes of the file
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: PathBuf::from("stats.json"),
+            flush_interval: 60,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct UserConfig {
     pub active: Option<bool>,
@@ -131,6 +307,78 @@ pub struct UserConfig {
     pub emote_prefix: Option<String>,
     pub twitch_emote_prefix: Option<String>,
     pub text_emote_prefix: Option<String>,
+    pub emote_correction: Option<EmoteCorrection>,
+    pub text_emotes: Option<Vec<TextEmote>>,
+    pub emote_sources: Option<Vec<EmoteSourceBinding>>,
+    pub transformers: Option<Vec<String>>,
+    pub private_emotes: Option<PathBuf>,
+    pub www_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceConfig {
+    TwitchManager {
+        host: String,
+        port: u16,
+    },
+    JsonIndex {
+        url: String,
+        #[serde(default = "SourceConfig::default_refresh_interval")]
+        refresh_interval: u64, // Seconds
+    },
+}
+
+impl SourceConfig {
+    fn default_refresh_interval() -> u64 {
+        60 * 60
+    }
+}
+
+/// The sources searched, in order, for emotes typed with `prefix`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmoteSourceBinding {
+    pub prefix: String,
+    pub sources: Vec<String>,
+}
+
+impl EmoteSourceBinding {
+    pub fn new(prefix: &str, sources: &[&str]) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            sources: sources.iter().map(|source| source.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextEmote {
+    pub triggers: Vec<String>,
+    pub text: String,
+}
+
+impl TextEmote {
+    pub fn new(triggers: &[&str], text: &str) -> Self {
+        Self {
+            triggers: triggers.iter().map(|trigger| trigger.to_string()).collect(),
+            text: text.to_owned(),
+        }
+    }
+}
+
+/// What to do when an emote name does not match any emote exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmoteCorrection {
+    AutoCorrect, // Send the closest emote if there is one clear candidate
+    Suggest, // Post the closest emote names
+    Off,
+}
+
+impl Default for EmoteCorrection {
+    fn default() -> Self {
+        EmoteCorrection::Off
+    }
 }
 
 impl WwwConfig {
@@ -0,0 +1,207 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    collections::HashMap,
+};
+
+use crate::{
+    config::ConflictPolicy,
+    emote_manager::Emote,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution {
+    Skipped, // The other emote was not loaded
+    Renamed(String), // The other emote was loaded under this qualified name
+    AliasRemoved, // The other emote was loaded without the conflicting alias
+    Unresolved,
+}
+
+/// Two emotes answering to the same qualified name or alias.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub name: String,
+    pub kept: PathBuf,
+    pub other: PathBuf,
+    pub resolution: Resolution,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\" is used by {} and {}", self.name, self.kept.display(), self.other.display())?;
+        match &self.resolution {
+            Resolution::Skipped => write!(f, ", the latter was not loaded"),
+            Resolution::Renamed(name) => write!(f, ", the latter was renamed to \"{}\"", name),
+            Resolution::AliasRemoved => write!(f, ", the alias was removed from the latter"),
+            Resolution::Unresolved => Ok(()),
+        }
+    }
+}
+
+/// Finds the emotes that share a name or an alias and resolves the conflicts with `policy`.
+/// `emotes` must be sorted by priority, the first emote using a name keeps it.
+pub fn resolve(emotes: Vec<Emote>, policy: ConflictPolicy) -> (Vec<Emote>, Vec<Conflict>) {
+    let mut taken: HashMap<String, PathBuf> = HashMap::new();
+    let mut resolved = Vec::with_capacity(emotes.len());
+    let mut conflicts = Vec::new();
+
+    for mut emote in emotes {
+        let name = emote.qualified_name();
+        if let Some(kept) = taken.get(&name) {
+            let resolution = match policy {
+                ConflictPolicy::Priority => Resolution::Skipped,
+                ConflictPolicy::Error => Resolution::Unresolved,
+                ConflictPolicy::Suffix => Resolution::Renamed(add_suffix(&mut emote, &taken)),
+            };
+            let renamed = matches!(resolution, Resolution::Renamed(_));
+            conflicts.push(Conflict {
+                name,
+                kept: kept.clone(),
+                other: emote.path.clone(),
+                resolution,
+            });
+            if !renamed {
+                continue;
+            }
+        }
+
+        let pack = emote.pack.clone();
+        let path = emote.path.clone();
+        emote.meta.aliases.retain(|alias| {
+            let alias_name = match &pack {
+                Some(pack) => format!("{}/{}", pack, alias),
+                None => alias.clone(),
+            };
+            match taken.get(&alias_name) {
+                Some(kept) => {
+                    conflicts.push(Conflict {
+                        name: alias_name,
+                        kept: kept.clone(),
+                        other: path.clone(),
+                        resolution: match policy {
+                            ConflictPolicy::Error => Resolution::Unresolved,
+                            _ => Resolution::AliasRemoved,
+                        },
+                    });
+                    false
+                },
+                None => true,
+            }
+        });
+
+        for name in emote.qualified_names() {
+            taken.entry(name).or_insert_with(|| emote.path.clone());
+        }
+        resolved.push(emote);
+    }
+
+    (resolved, conflicts)
+}
+
+/// Renames the emote to the first free `name_2`, `name_3`... and returns its new qualified name.
+fn add_suffix(emote: &mut Emote, taken: &HashMap<String, PathBuf>) -> String {
+    let base_name = emote.name.clone();
+    for n in 2.. {
+        emote.name = format!("{}_{}", base_name, n);
+        if !taken.contains_key(&emote.qualified_name()) {
+            break;
+        }
+    }
+    emote.qualified_name()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Resolution::*;
+
+    // Emotes by priority as (path, pack, name, aliases), policy, loaded emotes as (path, qualified name, aliases),
+    // conflicts as (name, kept path, other path, resolution)
+    type ResolveCase = (
+        Vec<(&'static str, Option<&'static str>, &'static str, Vec<&'static str>)>,
+        ConflictPolicy,
+        Vec<(&'static str, &'static str, Vec<&'static str>)>,
+        Vec<(&'static str, &'static str, &'static str, Resolution)>,
+    );
+
+    fn emote(path: &str, pack: Option<&str>, name: &str, aliases: &[&str]) -> Emote {
+        let mut emote = Emote::from_bytes(name.to_owned(), format!("{}.png", name), Vec::new());
+        emote.path = PathBuf::from(path);
+        emote.pack = pack.map(String::from);
+        emote.meta.aliases = aliases.iter().map(|alias| (*alias).to_owned()).collect();
+        emote
+    }
+
+    #[test]
+    fn resolve_conflicts() {
+        let cases: Vec<ResolveCase> = vec![
+            // No conflict
+            (vec![ ("a", None, "kappa", vec![]), ("b", None, "pog", vec![]) ], ConflictPolicy::Priority,
+                vec![ ("a", "kappa", vec![]), ("b", "pog", vec![]) ], vec![]),
+            (vec![ ("a", Some("pepe"), "sad", vec![]), ("b", Some("frog"), "sad", vec![]) ], ConflictPolicy::Error,
+                vec![ ("a", "pepe/sad", vec![]), ("b", "frog/sad", vec![]) ], vec![]),
+            // Same name, the first emote keeps it
+            (vec![ ("a", None, "kappa", vec![]), ("b", None, "kappa", vec![]) ], ConflictPolicy::Priority,
+                vec![ ("a", "kappa", vec![]) ], vec![ ("kappa", "a", "b", Skipped) ]),
+            (vec![ ("a", None, "kappa", vec![]), ("b", None, "kappa", vec![]) ], ConflictPolicy::Error,
+                vec![ ("a", "kappa", vec![]) ], vec![ ("kappa", "a", "b", Unresolved) ]),
+            (vec![ ("a", None, "kappa", vec![]), ("b", None, "kappa", vec![]), ("c", None, "kappa", vec![]) ], ConflictPolicy::Suffix,
+                vec![ ("a", "kappa", vec![]), ("b", "kappa_2", vec![]), ("c", "kappa_3", vec![]) ],
+                vec![ ("kappa", "a", "b", Renamed("kappa_2".into())), ("kappa", "a", "c", Renamed("kappa_3".into())) ]),
+            // The suffix skips the names that are taken
+            (vec![ ("a", None, "kappa", vec![]), ("b", None, "kappa_2", vec![]), ("c", None, "kappa", vec![]) ], ConflictPolicy::Suffix,
+                vec![ ("a", "kappa", vec![]), ("b", "kappa_2", vec![]), ("c", "kappa_3", vec![]) ],
+                vec![ ("kappa", "a", "c", Renamed("kappa_3".into())) ]),
+            (vec![ ("a", Some("pepe"), "sad", vec![]), ("b", Some("pepe"), "sad", vec![]) ], ConflictPolicy::Suffix,
+                vec![ ("a", "pepe/sad", vec![]), ("b", "pepe/sad_2", vec![]) ], vec![ ("pepe/sad", "a", "b", Renamed("pepe/sad_2".into())) ]),
+            // An alias of an emote with a higher priority wins over a name
+            (vec![ ("a", None, "kappa", vec![ "kap" ]), ("b", None, "kap", vec![]) ], ConflictPolicy::Priority,
+                vec![ ("a", "kappa", vec![ "kap" ]) ], vec![ ("kap", "a", "b", Skipped) ]),
+            // A name of an emote with a higher priority wins over an alias, which is removed
+            (vec![ ("a", None, "kappa", vec![]), ("b", None, "pog", vec![ "kappa", "p" ]) ], ConflictPolicy::Priority,
+                vec![ ("a", "kappa", vec![]), ("b", "pog", vec![ "p" ]) ], vec![ ("kappa", "a", "b", AliasRemoved) ]),
+            (vec![ ("a", None, "kappa", vec![]), ("b", None, "pog", vec![ "kappa" ]) ], ConflictPolicy::Error,
+                vec![ ("a", "kappa", vec![]), ("b", "pog", vec![]) ], vec![ ("kappa", "a", "b", Unresolved) ]),
+            (vec![ ("a", None, "kappa", vec![ "k" ]), ("b", None, "pog", vec![ "k" ]) ], ConflictPolicy::Suffix,
+                vec![ ("a", "kappa", vec![ "k" ]), ("b", "pog", vec![]) ], vec![ ("k", "a", "b", AliasRemoved) ]),
+            // Aliases are qualified with the pack
+            (vec![ ("a", Some("pepe"), "sad", vec![]), ("b", Some("pepe"), "happy", vec![ "sad" ]), ("c", Some("frog"), "happy", vec![ "sad" ]) ], ConflictPolicy::Priority,
+                vec![ ("a", "pepe/sad", vec![]), ("b", "pepe/happy", vec![]), ("c", "frog/happy", vec![ "sad" ]) ],
+                vec![ ("pepe/sad", "a", "b", AliasRemoved) ]),
+            // A renamed emote still loses its conflicting aliases
+            (vec![ ("a", None, "kappa", vec![ "k" ]), ("b", None, "kappa", vec![ "k" ]) ], ConflictPolicy::Suffix,
+                vec![ ("a", "kappa", vec![ "k" ]), ("b", "kappa_2", vec![]) ],
+                vec![ ("kappa", "a", "b", Renamed("kappa_2".into())), ("k", "a", "b", AliasRemoved) ]),
+        ];
+
+        for (emotes, policy, expected_loaded, expected_conflicts) in cases {
+            let description = format!("emotes: {:?}, policy: {:?}", emotes, policy);
+            let emotes = emotes
+                            .iter()
+                            .map(|(path, pack, name, aliases)| emote(path, *pack, name, aliases))
+                            .collect();
+            let (loaded, conflicts) = resolve(emotes, policy);
+
+            let loaded = loaded
+                            .iter()
+                            .map(|emote| (emote.path.to_string_lossy().into_owned(), emote.qualified_name(), emote.meta.aliases.clone()))
+                            .collect::<Vec<_>>();
+            let expected_loaded = expected_loaded
+                                    .into_iter()
+                                    .map(|(path, name, aliases)| (path.to_owned(), name.to_owned(), aliases.into_iter().map(String::from).collect::<Vec<_>>()))
+                                    .collect::<Vec<_>>();
+            assert_eq!(loaded, expected_loaded, "{}", description);
+
+            let conflicts = conflicts
+                                .into_iter()
+                                .map(|conflict| (conflict.name, conflict.kept, conflict.other, conflict.resolution))
+                                .collect::<Vec<_>>();
+            let expected_conflicts = expected_conflicts
+                                        .into_iter()
+                                        .map(|(name, kept, other, resolution)| (name.to_owned(), PathBuf::from(kept), PathBuf::from(other), resolution))
+                                        .collect::<Vec<_>>();
+            assert_eq!(conflicts, expected_conflicts, "{}", description);
+        }
+    }
+}
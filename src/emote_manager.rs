@@ -1,148 +1,1278 @@
 use std::{
-    sync::Arc,
+    fs::File,
+    ops::Deref,
+    sync::{ Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, mpsc, atomic::{ AtomicBool, Ordering } },
     path::{ Path, PathBuf },
+    hash::Hash,
+    time::Duration,
+    collections::HashMap,
 };
 
 use crate::{
-    www::library,
-    config::Config,
+    media::{ self, MediaFormat },
+    combine,
+    modifiers::{ self, Modifier },
+    conflicts::{ self, Conflict },
+    validation::{ self, InvalidAsset, ValidationSummary },
+    sources::{ self, EmoteSource, LOCAL_SOURCE },
+    config::{ Config, EmotesConfig, CategoryConfig, ConflictPolicy, TextEmote },
     Error, ErrorKind, Result,
 };
 
+use lru::LruCache;
+use memmap::Mmap;
 use typemap::Key;
 use serde::Deserialize;
+use notify::{ Watcher, RecursiveMode, DebouncedEvent };
+
+const MANIFEST_FILE: &str = "emotes.toml";
+
+const MIN_SIMILARITY: f64 = 0.5;
+const AUTOCORRECT_SIMILARITY: f64 = 0.75;
+const AUTOCORRECT_MARGIN: f64 = 0.1;
+
+/// The contents of an emote file, only loaded when the emote is about to be sent.
+pub enum Payload {
+    Memory(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Payload::Memory(bytes) => bytes.as_slice(),
+            Payload::Mapped(mmap) => &mmap[..],
+        }
+    }
+}
+
+/// Extra information about an emote, read from the optional manifest file at the root of the assets directory.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+pub struct EmoteMeta {
+    pub display_name: Option<String>,
+    pub aliases: Vec<String>,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+}
 
 pub struct Emote {
     pub path: PathBuf,
     pub file_name: String,
     pub name: String,
-    pub bytes: Vec<u8>,
+    pub category: String, // Directory of the category, empty for remote emotes
+    pub pack: Option<String>, // Subdirectories of the category the emote is in, e.g. `pepe/sad`
+    pub size: u64,
+    pub meta: EmoteMeta,
+    bytes: Option<Arc<Payload>>, // Set for emotes that do not live on disk, such as Twitch emotes, or loaded by another manager
 }
 
 impl Emote {
-    pub fn as_attachment(&self) -> (&[u8], &str) {
-        (self.bytes.as_slice(), &self.file_name)
+    pub fn from_file(path: PathBuf, category: &str, pack: Option<String>) -> std::io::Result<Self> {
+        let size = path.metadata()?.len();
+        let file_name = path.file_name()
+                            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "no file name"))?
+                            .to_string_lossy()
+                            .into();
+        let name = path.with_extension("")
+                        .file_name()
+                        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "no file name"))?
+                        .to_string_lossy()
+                        .to_lowercase();
+
+        Ok(Self {
+            path,
+            file_name,
+            name,
+            category: category.to_owned(),
+            pack,
+            size,
+            meta: EmoteMeta::default(),
+            bytes: None,
+        })
+    }
+
+    pub fn from_bytes(name: String, file_name: String, bytes: Vec<u8>) -> Self {
+        Self::from_payload(name, file_name, Arc::new(Payload::Memory(bytes)))
+    }
+
+    pub fn from_payload(name: String, file_name: String, payload: Arc<Payload>) -> Self {
+        Self {
+            path: PathBuf::new(),
+            file_name,
+            name,
+            category: String::new(),
+            pack: None,
+            size: payload.len() as u64,
+            meta: EmoteMeta::default(),
+            bytes: Some(payload),
+        }
+    }
+
+    /// Whether `name` is the name or one of the aliases of this emote. `name` must be lowercase.
+    pub fn has_name(&self, name: &str) -> bool {
+        self.name == name || self.meta.aliases.iter().any(|alias| alias == name)
+    }
+
+    /// The name prefixed with the pack, which always designates this emote.
+    pub fn qualified_name(&self) -> String {
+        match &self.pack {
+            Some(pack) => format!("{}/{}", pack, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// The qualified name followed by the aliases qualified with the pack.
+    pub fn qualified_names(&self) -> Vec<String> {
+        std::iter::once(&self.name)
+            .chain(self.meta.aliases.iter())
+            .map(|name| match &self.pack {
+                Some(pack) => format!("{}/{}", pack, name),
+                None => name.clone(),
+            })
+            .collect()
+    }
+
+    /// Reads the emote file from disk, or maps it into memory if `mmap` is set.
+    /// Mapped files must only ever be replaced, never modified in place, see `EmoteManager::mmap`.
+    pub fn load(&self, mmap: bool) -> std::io::Result<Arc<Payload>> {
+        if let Some(bytes) = &self.bytes {
+            return Ok(bytes.clone());
+        }
+
+        let payload = if mmap && self.size > 0 {
+            let file = File::open(&self.path)?;
+            // SAFETY: the mapping stays valid as long as the file is not truncated or written to.
+            // The bot only replaces emote files by renaming complete files over them (uploads, renames and removals),
+            // which leaves the old inode and the mappings of it untouched. Files edited in place by other programs
+            // would be seen by the watcher, which then stops mapping files, see `EmoteManager::mmap`.
+            Payload::Mapped(unsafe { Mmap::map(&file)? })
+        } else {
+            Payload::Memory(std::fs::read(&self.path)?)
+        };
+        Ok(Arc::new(payload))
+    }
+
+    pub fn as_attachment<'a>(&'a self, payload: &'a Payload) -> (&'a [u8], &'a str) {
+        (&payload[..], &self.file_name)
+    }
+}
+
+/// The emotes found in the assets directory.
+pub struct Scan {
+    pub emotes: Vec<Arc<Emote>>,
+    pub conflicts: Vec<Conflict>,
+    pub invalid: Vec<InvalidAsset>,
+}
+
+/// Keeps the most recently sent payloads in memory, up to `capacity` bytes.
+struct PayloadCache<K: Hash + Eq> {
+    entries: LruCache<K, Arc<Payload>>,
+    size: u64,
+    capacity: u64,
+}
+
+impl<K: Hash + Eq> PayloadCache<K> {
+    fn new(capacity: u64) -> Self {
+        Self {
+            entries: LruCache::unbounded(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<Arc<Payload>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn put(&mut self, key: K, payload: Arc<Payload>) {
+        let len = payload.len() as u64;
+        if len > self.capacity {
+            return;
+        }
+
+        self.remove(&key);
+        self.size += len;
+        self.entries.put(key, payload);
+        while self.size > self.capacity {
+            match self.entries.pop_lru() {
+                Some((_key, evicted)) => self.size -= evicted.len() as u64,
+                None => break,
+            };
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(payload) = self.entries.pop(key) {
+            self.size -= payload.len() as u64;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
     }
 }
 
 pub struct EmoteManager {
-    emotes: Vec<Emote>,
-    text_emotes: Vec<(Vec<&'static str>, &'static str)>,
-    www_config: crate::config::WwwConfig,
+    assets_directory: PathBuf,
+    emotes: RwLock<Vec<Arc<Emote>>>,
+    manifest: RwLock<HashMap<String, EmoteMeta>>,
+    conflicts: RwLock<Vec<Conflict>>, // Found by the last reload
+    invalid_assets: RwLock<Vec<InvalidAsset>>,
+    payload_cache: Mutex<PayloadCache<PathBuf>>,
+    modified_cache: Mutex<PayloadCache<String>>, // Emotes transformed by modifiers
+    emotes_config: EmotesConfig,
+    mmap_unsafe: AtomicBool, // Set once a file was modified in place, payloads are read into memory from then on
+    text_emotes: Vec<TextEmote>,
+    sources: HashMap<String, Box<dyn EmoteSource>>,
 }
 
 impl EmoteManager {
-    pub fn new(config: &Config, assets_directory: &Path) -> Result<Self> {
-        let mut mngr = Self {
-            emotes: Vec::new(),
-            text_emotes: vec![
-                (vec!["lf", "lennyface", "lenny"], "( ͡° ͜ʖ ͡°)"),
-                (vec!["shrug", "s"], r"¯\\\_(ツ)\_/¯"),
-            ],
-            www_config: config.www.clone(),
-        };
+    pub fn new(config: &Config) -> Result<Self> {
+        let text_emotes = vec![
+            TextEmote::new(&["lf", "lennyface", "lenny"], "( ͡° ͜ʖ ͡°)"),
+            TextEmote::new(&["shrug", "s"], r"¯\\\_(ツ)\_/¯"),
+        ].into_iter().chain(config.text_emotes.iter().cloned()).collect();
+        Self::with_library(config.emotes.clone(), text_emotes, sources::build(config)?)
+    }
 
-        for dir in ["emojis", "gifs", "sounds"].iter() {
-            let mut path = PathBuf::from(assets_directory);
-            path.push(dir);
-            mngr.load_emotes_in_dir(&path).map_err(|err| Error::from(ErrorKind::LoadEmotes, err))?;
+    /// The private emotes of a user, kept in `directory` with the same categories as the shared library.
+    /// The missing category directories are created, and there are no remote sources nor text emotes.
+    pub fn private(config: &EmotesConfig, directory: &Path) -> Result<Self> {
+        let mut emotes_config = config.clone();
+        emotes_config.directory = directory.to_path_buf();
+        for category in emotes_config.categories.iter() {
+            std::fs::create_dir_all(directory.join(&category.directory)).map_err(|err| Error::from(ErrorKind::LoadEmotes, err))?;
         }
+        Self::with_library(emotes_config, Vec::new(), HashMap::new())
+    }
+
+    fn with_library(emotes_config: EmotesConfig, text_emotes: Vec<TextEmote>, sources: HashMap<String, Box<dyn EmoteSource>>) -> Result<Self> {
+        let mngr = Self {
+            assets_directory: emotes_config.directory.clone(),
+            emotes: RwLock::new(Vec::new()),
+            manifest: RwLock::new(HashMap::new()),
+            conflicts: RwLock::new(Vec::new()),
+            invalid_assets: RwLock::new(Vec::new()),
+            payload_cache: Mutex::new(PayloadCache::new(emotes_config.cache_size)),
+            modified_cache: Mutex::new(PayloadCache::new(emotes_config.modified_cache_size)),
+            emotes_config,
+            mmap_unsafe: AtomicBool::new(false),
+            text_emotes,
+            sources,
+        };
+        mngr.reload()?;
 
         Ok(mngr)
     }
 
-    fn load_emotes_in_dir(&mut self, dir: &Path) -> std::io::Result<()> {
+    /// Reads the manifest and every category directory again and swaps the whole emote list at once.
+    pub fn reload(&self) -> Result<()> {
+        let manifest = Self::load_manifest(&self.assets_directory)?;
+        let scan = Self::scan(&self.emotes_config, &manifest)?;
+        for conflict in scan.conflicts.iter() {
+            log::warn!("Emote name conflict: {}", conflict);
+        }
+        if self.emotes_config.conflict_policy == ConflictPolicy::Error && !scan.conflicts.is_empty() {
+            return Err(Error::with_message(
+                ErrorKind::EmoteConflict,
+                format!("{} emote name conflict(s), run with --conflicts for a report", scan.conflicts.len()),
+            ));
+        }
+
+        *self.manifest.write().map_err(|_err| Error::new(ErrorKind::ManagerWrite))? = manifest;
+        *self.conflicts.write().map_err(|_err| Error::new(ErrorKind::ManagerWrite))? = scan.conflicts;
+        *self.invalid_assets.write().map_err(|_err| Error::new(ErrorKind::ManagerWrite))? = scan.invalid;
+        *self.write_emotes()? = scan.emotes;
+        self.lock_payload_cache()?.clear();
+        self.lock_modified_cache()?.clear();
+        Ok(())
+    }
+
+    /// Loads the emotes of every category, in the order of the configuration,
+    /// skips the invalid assets and resolves the name conflicts between the others.
+    pub fn scan(config: &EmotesConfig, manifest: &HashMap<String, EmoteMeta>) -> Result<Scan> {
+        let mut emotes = Vec::new();
+        for category in config.categories.iter() {
+            let path = config.directory.join(&category.directory);
+            let mut category_emotes = Vec::new();
+            Self::load_emotes_in_dir(&path, category, None, manifest, &mut category_emotes).map_err(|err| Error::from(ErrorKind::LoadEmotes, err))?;
+            // The directory listing order is not specified, this makes the conflict resolution reproducible
+            category_emotes.sort_by(|a, b| a.path.cmp(&b.path));
+            emotes.append(&mut category_emotes);
+        }
+
+        let (emotes, invalid) = Self::validate(emotes, config.upload_limit);
+        let (emotes, conflicts) = conflicts::resolve(emotes, config.conflict_policy);
+        Ok(Scan {
+            emotes: emotes.into_iter().map(Arc::new).collect(),
+            conflicts,
+            invalid,
+        })
+    }
+
+    /// Separates the emotes that can be sent from the invalid ones, which are logged.
+    fn validate(emotes: Vec<Emote>, upload_limit: u64) -> (Vec<Emote>, Vec<InvalidAsset>) {
+        let mut valid = Vec::with_capacity(emotes.len());
+        let mut invalid = Vec::new();
+        for emote in emotes {
+            match validation::validate(&emote, upload_limit) {
+                Ok(()) => valid.push(emote),
+                Err(asset) => {
+                    log::warn!("Skipping invalid emote asset {}", asset);
+                    invalid.push(asset);
+                },
+            };
+        }
+        (valid, invalid)
+    }
+
+    pub fn load_manifest(assets_directory: &Path) -> Result<HashMap<String, EmoteMeta>> {
+        let path = assets_directory.join(MANIFEST_FILE);
+        let mut manifest = config::Config::default();
+        manifest
+            .merge(config::File::from(path).required(false))
+            .map_err(|err| Error::from(ErrorKind::Manifest, err))?;
+        let mut manifest: HashMap<String, EmoteMeta> = manifest.try_into().map_err(|err| Error::from(ErrorKind::Manifest, err))?;
+
+        for meta in manifest.values_mut() {
+            for alias in meta.aliases.iter_mut() {
+                *alias = alias.to_lowercase();
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Loads the emotes of a directory, its subdirectories being loaded as packs.
+    fn load_emotes_in_dir(dir: &Path, category: &CategoryConfig, pack: Option<&str>, manifest: &HashMap<String, EmoteMeta>, emotes: &mut Vec<Emote>) -> std::io::Result<()> {
         for entry in dir.read_dir()? {
             let entry = entry?;
             let path = entry.path();
 
-            if path.is_file() {
-                let bytes = std::fs::read(&path)?;
-                let file_name = path.file_name()
-                                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "no file name"))?
-                                    .to_string_lossy()
-                                    .into();
-                let name = path.with_extension("")
-                                .file_name()
-                                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "no file name"))?
-                                .to_string_lossy()
-                                .to_lowercase();
-
-                self.emotes.push(Emote {
-                    path,
-                    file_name,
-                    name,
-                    bytes,
-                });
+            if path.is_dir() {
+                let dir_name = entry.file_name().to_string_lossy().to_lowercase();
+                let sub_pack = match pack {
+                    Some(pack) => format!("{}/{}", pack, dir_name),
+                    None => dir_name,
+                };
+                Self::load_emotes_in_dir(&path, category, Some(&sub_pack), manifest, emotes)?;
+            } else if path.is_file() && category.accepts(&path) {
+                let mut emote = Emote::from_file(path, &category.directory, pack.map(str::to_owned))?;
+                if let Some(meta) = Self::manifest_entry(manifest, &emote) {
+                    emote.meta = meta.clone();
+                }
+                emotes.push(emote);
             }
         }
         Ok(())
     }
 
-    pub fn find_emote_by_name(&self, name: &str) -> Option<&Emote> {
-        let name = name.to_lowercase();
-        self.emotes.iter().find(|emote| emote.name == name)
+    /// Manifest entries are keyed by the qualified name of the emote, or by its name alone.
+    fn manifest_entry<'a>(manifest: &'a HashMap<String, EmoteMeta>, emote: &Emote) -> Option<&'a EmoteMeta> {
+        manifest.get(&emote.qualified_name()).or_else(|| manifest.get(&emote.name))
     }
 
-    pub fn find_twitch_emote_urls(&self, query: &str, limit: usize, exact_match: bool) -> Result<Vec<library::Emote>> {
-        let query = query.to_lowercase();
-        let limit = limit.clamp(0, 50);
+    /// Spawns a thread that watches the category directories and keeps the emote list up to date.
+    /// Emotes that are being sent while the list changes are kept alive by their `Arc`.
+    pub fn watch(mngr: Arc<EmoteManager>) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::watcher(tx, Duration::from_secs(2))?;
+        watcher.watch(&mngr.assets_directory, RecursiveMode::NonRecursive)?; // For the manifest
+        for category in mngr.emotes_config.categories.iter() {
+            watcher.watch(mngr.assets_directory.join(&category.directory), RecursiveMode::Recursive)?;
+        }
+
+        std::thread::spawn(move || {
+            // Moved into the thread so that it is not dropped, which would stop the watch
+            let _watcher = watcher;
+
+            while let Ok(event) = rx.recv() {
+                if let Err(err) = mngr.handle_watch_event(event) {
+                    log::error!("Error while reloading emotes: {}", err);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    fn handle_watch_event(&self, event: DebouncedEvent) -> Result<()> {
+        match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) if Self::is_manifest(&path) => {
+                log::info!("Emote manifest changed, reloading emotes...");
+                self.reload()
+            },
+            DebouncedEvent::Rename(from, to) if Self::is_manifest(&from) || Self::is_manifest(&to) => {
+                log::info!("Emote manifest changed, reloading emotes...");
+                self.reload()
+            },
+            DebouncedEvent::Write(path) => {
+                if self.mmap() {
+                    log::warn!("{} was modified in place, emote files will not be mapped into memory anymore. Replace files by renaming new ones over them instead.", path.display());
+                    self.mmap_unsafe.store(true, Ordering::SeqCst);
+                }
+                self.load_emote(&path)
+            },
+            DebouncedEvent::Create(path) => self.load_emote(&path),
+            DebouncedEvent::Remove(path) => self.unload_emote(&path),
+            DebouncedEvent::Rename(from, to) => {
+                self.unload_emote(&from)?;
+                self.load_emote(&to)
+            },
+            DebouncedEvent::Rescan => {
+                log::info!("Rescanning emote directories...");
+                self.reload()
+            },
+            DebouncedEvent::Error(err, _path) => Err(Error::from(ErrorKind::Watcher, err)),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_manifest(path: &Path) -> bool {
+        path.file_name().map_or(false, |name| name == MANIFEST_FILE)
+    }
 
-        #[derive(Debug, Deserialize)]
-        struct ManagerSearchResult {
-            pub id: u64,
-            pub name: String,
-            pub url: String,
+    /// Maps a path reported by the watcher back to the same form as the paths built by `reload`,
+    /// since some platforms report canonicalized paths. Also returns the category and the pack of the path.
+    fn asset_path(&self, path: &Path) -> Option<(PathBuf, &CategoryConfig, Option<String>)> {
+        let relative = match path.strip_prefix(&self.assets_directory) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_err) => path.strip_prefix(self.assets_directory.canonicalize().ok()?).ok()?.to_path_buf(),
         };
+        let mut components = relative.iter().map(|component| component.to_string_lossy().into_owned()).collect::<Vec<_>>();
+        if components.len() < 2 {
+            return None;
+        }
 
-        let url = format!(
-            "http://{}:{}/emotes/search?q={}&maxresults={}&exactmatch={}",
-            self.www_config.twitch_emotes_manager_host,
-            self.www_config.twitch_emotes_manager_port,
-            query, limit, if exact_match { 1 } else { 0 },
-        );
-        let res = reqwest::blocking::get(&url)?;
-        let res: Vec<ManagerSearchResult> = res.json()?;
+        let category = self.category(&components.remove(0))?;
+        components.pop(); // File name
+        let pack = if components.is_empty() {
+            None
+        } else {
+            Some(components.join("/").to_lowercase())
+        };
+        Some((self.assets_directory.join(relative), category, pack))
+    }
+
+    /// Loads or reloads an emote file, or all the emotes of a pack directory.
+    fn load_emote(&self, path: &Path) -> Result<()> {
+        let (path, category, pack) = match self.asset_path(path) {
+            Some(asset) => asset,
+            None => return Ok(()),
+        };
+
+        let mut loaded = Vec::new();
+        {
+            let manifest = self.manifest.read().map_err(|_err| Error::new(ErrorKind::ManagerRead))?;
+            if path.is_dir() {
+                let dir_name = path.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+                let pack = match pack {
+                    Some(pack) => format!("{}/{}", pack, dir_name),
+                    None => dir_name,
+                };
+                Self::load_emotes_in_dir(&path, category, Some(&pack), &manifest, &mut loaded).map_err(|err| Error::from(ErrorKind::LoadEmotes, err))?;
+            } else if path.is_file() && category.accepts(&path) {
+                let mut emote = Emote::from_file(path.clone(), &category.directory, pack).map_err(|err| Error::from(ErrorKind::LoadEmotes, err))?;
+                if let Some(meta) = Self::manifest_entry(&manifest, &emote) {
+                    emote.meta = meta.clone();
+                }
+                loaded.push(emote);
+            }
+        }
+
+        let (loaded, invalid) = Self::validate(loaded, self.emotes_config.upload_limit);
+        {
+            let mut invalid_assets = self.invalid_assets.write().map_err(|_err| Error::new(ErrorKind::ManagerWrite))?;
+            invalid_assets.retain(|asset| !asset.path.starts_with(&path));
+            invalid_assets.extend(invalid);
+        }
 
-        Ok(res.iter().map(|e| library::Emote {
-            name: e.name.clone(),
-            url: e.url.replace("2.0", "3.0").replace("1.0", "3.0"),
-        }).collect())
+        // The resolution of a conflict depends on all the emotes, the library is loaded again from scratch
+        let conflicting = {
+            let emotes = self.read_emotes()?;
+            loaded.iter().any(|new| {
+                let names = new.qualified_names();
+                emotes.iter().any(|emote| emote.path != new.path && emote.qualified_names().iter().any(|name| names.contains(name)))
+            })
+        };
+        if conflicting {
+            log::info!("Emote name conflict in {}, reloading emotes...", path.display());
+            return self.reload();
+        }
+
+        let mut payload_cache = self.lock_payload_cache()?;
+        self.lock_modified_cache()?.clear();
+        let mut emotes = self.write_emotes()?;
+        // Emotes whose file became invalid
+        emotes.retain(|emote| {
+            let keep = !emote.path.starts_with(&path) || loaded.iter().any(|new| new.path == emote.path);
+            if !keep {
+                payload_cache.remove(&emote.path);
+                log::info!("Removed emote \"{}\".", emote.qualified_name());
+            }
+            keep
+        });
+        for emote in loaded.into_iter().map(Arc::new) {
+            payload_cache.remove(&emote.path);
+            match emotes.iter_mut().find(|e| e.path == emote.path) {
+                Some(existing) => {
+                    log::info!("Reloaded emote \"{}\".", emote.qualified_name());
+                    *existing = emote;
+                },
+                None => {
+                    log::info!("Added emote \"{}\".", emote.qualified_name());
+                    emotes.push(emote);
+                },
+            };
+        }
+        Ok(())
     }
 
-    pub fn find_twitch_emote(&self, name: &str) -> Result<Option<Emote>> {
+    /// Unloads an emote file, or all the emotes of a pack directory.
+    fn unload_emote(&self, path: &Path) -> Result<()> {
+        let path = match self.asset_path(path) {
+            Some((path, _category, _pack)) => path,
+            None => return Ok(()),
+        };
+        self.invalid_assets
+            .write()
+            .map_err(|_err| Error::new(ErrorKind::ManagerWrite))?
+            .retain(|asset| !asset.path.starts_with(&path));
+
+        // An emote that was renamed or skipped because of this one can now get its name back
+        if self.had_conflict(&path)? {
+            log::info!("Emote name conflict in {} removed, reloading emotes...", path.display());
+            return self.reload();
+        }
+
+        let mut payload_cache = self.lock_payload_cache()?;
+        self.lock_modified_cache()?.clear();
+        let mut emotes = self.write_emotes()?;
+        emotes.retain(|emote| {
+            if emote.path.starts_with(&path) {
+                payload_cache.remove(&emote.path);
+                log::info!("Removed emote \"{}\".", emote.qualified_name());
+                false
+            } else {
+                true
+            }
+        });
+        Ok(())
+    }
+
+    fn read_emotes(&self) -> Result<RwLockReadGuard<Vec<Arc<Emote>>>> {
+        self.emotes.read().map_err(|_err| Error::new(ErrorKind::ManagerRead))
+    }
+
+    fn write_emotes(&self) -> Result<RwLockWriteGuard<Vec<Arc<Emote>>>> {
+        self.emotes.write().map_err(|_err| Error::new(ErrorKind::ManagerWrite))
+    }
+
+    fn lock_payload_cache(&self) -> Result<MutexGuard<PayloadCache<PathBuf>>> {
+        self.payload_cache.lock().map_err(|_err| Error::new(ErrorKind::ManagerWrite))
+    }
+
+    fn lock_modified_cache(&self) -> Result<MutexGuard<PayloadCache<String>>> {
+        self.modified_cache.lock().map_err(|_err| Error::new(ErrorKind::ManagerWrite))
+    }
+
+    /// Writes a new emote into the library and loads it right away. The emote goes into `category`,
+    /// or by default into the first category that lists its format, and `name` may include a pack (`pack/name`).
+    pub fn add_emote(&self, name: &str, category: Option<&str>, bytes: Vec<u8>, content_type: Option<&str>) -> Result<Arc<Emote>> {
+        let (pack, base_name) = self.new_name(name)?;
+
+        let (bytes, format) = media::normalize(bytes, content_type)?;
+        if format == MediaFormat::Unknown {
+            return Err(Error::with_message(ErrorKind::EditLibrary, "the file is not a supported image or sound".into()));
+        }
+        let file_name = format!("{}.{}", base_name, format.extension());
+
+        let category = match category {
+            Some(directory) => self.category(&directory.to_lowercase())
+                                    .ok_or_else(|| Error::with_message(ErrorKind::EditLibrary, format!("there is no category \"{}\"", directory)))?,
+            None => self.default_category(format)
+                        .ok_or_else(|| Error::with_message(ErrorKind::EditLibrary, format!("no category accepts .{} files", format.extension())))?,
+        };
+        if !category.accepts(Path::new(&file_name)) {
+            return Err(Error::with_message(ErrorKind::EditLibrary, format!("the {} category does not accept .{} files", category.display_name, format.extension())));
+        }
+
+        let mut dir = self.assets_directory.join(&category.directory);
+        if let Some(pack) = &pack {
+            dir.push(pack);
+        }
+        let path = dir.join(&file_name);
+        if path.exists() {
+            return Err(Error::with_message(ErrorKind::EditLibrary, format!("{} already exists", path.display())));
+        }
+
+        // Written next to the categories first, so that the watcher never sees a partial or invalid file
+        let tmp_path = self.assets_directory.join(format!(".{}.tmp", file_name));
+        std::fs::write(&tmp_path, &bytes).map_err(|err| Error::from(ErrorKind::EditLibrary, err))?;
+        let validated = Emote::from_file(tmp_path.clone(), &category.directory, pack)
+                            .map_err(|err| Error::from(ErrorKind::EditLibrary, err))
+                            .and_then(|emote| validation::validate(&emote, self.emotes_config.upload_limit)
+                                                .map_err(|asset| Error::with_message(ErrorKind::EditLibrary, asset.problem.to_string())));
+        let moved = validated.and_then(|()| {
+            std::fs::create_dir_all(&dir)
+                .and_then(|()| std::fs::rename(&tmp_path, &path))
+                .map_err(|err| Error::from(ErrorKind::EditLibrary, err))
+        });
+        if let Err(err) = moved {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+
+        self.load_emote(&path)?;
+        match self.read_emotes()?.iter().find(|emote| emote.path == path) {
+            Some(emote) => Ok(emote.clone()),
+            None => {
+                let _ = std::fs::remove_file(&path);
+                Err(Error::with_message(ErrorKind::EditLibrary, "the emote could not be loaded".into()))
+            },
+        }
+    }
+
+    /// Renames an emote, moving its file into the directory of the new pack if the name has one.
+    /// The file is moved while the emote list is locked, so the emote is never seen missing or under both names.
+    /// The manifest entry of the emote is moved to the new name in the manifest file as well, see `rename_manifest_entry`.
+    pub fn rename_emote(&self, name: &str, new_name: &str) -> Result<Arc<Emote>> {
+        let emote = self.find_local_emote(name)?;
+        let (pack, base_name) = self.new_name(new_name)?;
+
+        let mut dir = self.assets_directory.join(&emote.category);
+        if let Some(pack) = &pack {
+            dir.push(pack);
+        }
+        let path = match emote.path.extension() {
+            Some(extension) => dir.join(base_name).with_extension(extension),
+            None => dir.join(base_name),
+        };
+        if path.exists() {
+            return Err(Error::with_message(ErrorKind::EditLibrary, format!("{} already exists", path.display())));
+        }
+
+        let renamed = {
+            let mut manifest = self.manifest.write().map_err(|_err| Error::new(ErrorKind::ManagerWrite))?;
+            let mut payload_cache = self.lock_payload_cache()?;
+            self.lock_modified_cache()?.clear();
+            let mut emotes = self.write_emotes()?;
+
+            std::fs::create_dir_all(&dir)
+                .and_then(|()| std::fs::rename(&emote.path, &path))
+                .map_err(|err| Error::from(ErrorKind::EditLibrary, err))?;
+            let mut renamed = match Emote::from_file(path.clone(), &emote.category, pack) {
+                Ok(renamed) => renamed,
+                Err(err) => {
+                    let _ = std::fs::rename(&path, &emote.path);
+                    return Err(Error::from(ErrorKind::EditLibrary, err));
+                },
+            };
+            if let Err(err) = self.rename_manifest_entry(&mut manifest, &emote, &renamed) {
+                let _ = std::fs::rename(&path, &emote.path);
+                return Err(err);
+            }
+            if let Some(meta) = Self::manifest_entry(&manifest, &renamed) {
+                renamed.meta = meta.clone();
+            }
+
+            let renamed = Arc::new(renamed);
+            payload_cache.remove(&emote.path);
+            if let Some(existing) = emotes.iter_mut().find(|e| e.path == emote.path) {
+                *existing = renamed.clone();
+            }
+            renamed
+        };
+        log::info!("Renamed emote \"{}\" to \"{}\".", emote.qualified_name(), renamed.qualified_name());
+
+        if self.had_conflict(&emote.path)? {
+            self.reload()?;
+        }
+        Ok(renamed)
+    }
+
+    /// Moves the manifest entry of an emote to the qualified name of `renamed`, in memory and in the manifest file.
+    /// The file is parsed and written again, so its comments and the order of its entries are not kept.
+    /// It is replaced rather than edited in place, and the watcher reloads the library once it sees it.
+    fn rename_manifest_entry(&self, manifest: &mut HashMap<String, EmoteMeta>, emote: &Emote, renamed: &Emote) -> Result<()> {
+        let key = if manifest.contains_key(&emote.qualified_name()) {
+            emote.qualified_name()
+        } else if manifest.contains_key(&emote.name) {
+            emote.name.clone()
+        } else {
+            return Ok(());
+        };
+
+        let path = self.assets_directory.join(MANIFEST_FILE);
+        let contents = std::fs::read_to_string(&path).map_err(|err| Error::from(ErrorKind::Manifest, err))?;
+        let mut entries: toml::value::Table = toml::from_str(&contents).map_err(|err| Error::from(ErrorKind::Manifest, err))?;
+        if let Some(entry) = entries.remove(&key) {
+            entries.insert(renamed.qualified_name(), entry);
+        }
+        let contents = toml::to_string(&entries).map_err(|err| Error::from(ErrorKind::Manifest, err))?;
+
+        let tmp_path = self.assets_directory.join(format!(".{}.tmp", MANIFEST_FILE));
+        std::fs::write(&tmp_path, contents)
+            .and_then(|()| std::fs::rename(&tmp_path, &path))
+            .map_err(|err| Error::from(ErrorKind::EditLibrary, err))?;
+
+        if let Some(meta) = manifest.remove(&key) {
+            manifest.insert(renamed.qualified_name(), meta);
+        }
+        Ok(())
+    }
+
+    /// Moves an emote file into a timestamped directory of the trash, where it keeps its path relative to the assets directory
+    /// so that it can be restored by moving it back. Returns the path of the file in the trash.
+    pub fn remove_emote(&self, name: &str) -> Result<PathBuf> {
+        let emote = self.find_local_emote(name)?;
+        let relative = emote.path
+                            .strip_prefix(&self.assets_directory)
+                            .map_err(|err| Error::from(ErrorKind::EditLibrary, err))?;
+        let trash_path = self.assets_directory
+                                .join(&self.emotes_config.trash_directory)
+                                .join(chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string())
+                                .join(relative);
+
+        {
+            let mut payload_cache = self.lock_payload_cache()?;
+            self.lock_modified_cache()?.clear();
+            let mut emotes = self.write_emotes()?;
+
+            trash_path.parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::rename(&emote.path, &trash_path))
+                .map_err(|err| Error::from(ErrorKind::EditLibrary, err))?;
+            payload_cache.remove(&emote.path);
+            emotes.retain(|e| e.path != emote.path);
+        }
+        log::info!("Moved emote \"{}\" to {}.", emote.qualified_name(), trash_path.display());
+
+        if self.had_conflict(&emote.path)? {
+            self.reload()?;
+        }
+        Ok(trash_path)
+    }
+
+    /// Finds an emote of the library by name, for the commands that edit it.
+    fn find_local_emote(&self, name: &str) -> Result<Arc<Emote>> {
+        self.find_emote_by_name(name)?
+            .filter(|emote| !emote.category.is_empty())
+            .ok_or_else(|| Error::with_message(ErrorKind::EditLibrary, format!("there is no emote named \"{}\"", name)))
+    }
+
+    /// Checks that `name` can be given to a new emote and splits it into its pack and its name.
+    fn new_name(&self, name: &str) -> Result<(Option<String>, String)> {
         let name = name.to_lowercase();
+        let is_valid_component = |component: &str| !component.is_empty() && component.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !name.split('/').all(is_valid_component) {
+            return Err(Error::with_message(ErrorKind::EditLibrary, format!("\"{}\" is not a valid emote name", name)));
+        }
+        if self.read_emotes()?.iter().any(|emote| emote.qualified_names().contains(&name)) {
+            return Err(Error::with_message(ErrorKind::EditLibrary, format!("an emote named \"{}\" already exists", name)));
+        }
+
+        Ok(match name.rsplit_once('/') {
+            Some((pack, base_name)) => (Some(pack.to_owned()), base_name.to_owned()),
+            None => (None, name),
+        })
+    }
+
+    /// Whether a file or directory was involved in a name conflict when the library was last loaded.
+    fn had_conflict(&self, path: &Path) -> Result<bool> {
+        Ok(self.conflicts
+                .read()
+                .map_err(|_err| Error::new(ErrorKind::ManagerRead))?
+                .iter()
+                .any(|conflict| conflict.kept.starts_with(path) || conflict.other.starts_with(path)))
+    }
+
+    /// The category that lists the extension of `format` first, or else the first category that accepts any file.
+    fn default_category(&self, format: MediaFormat) -> Option<&CategoryConfig> {
+        let extension = format.extension();
+        self.emotes_config.categories
+            .iter()
+            .filter_map(|category| {
+                if category.extensions.is_empty() {
+                    Some((usize::MAX, category))
+                } else {
+                    category.extensions
+                        .iter()
+                        .position(|ext| ext.eq_ignore_ascii_case(extension))
+                        .map(|idx| (idx, category))
+                }
+            })
+            .min_by_key(|(idx, _category)| *idx)
+            .map(|(_idx, category)| category)
+    }
+
+    /// Whether emote files are mapped into memory instead of read.
+    /// A mapped file that is truncated or rewritten in place crashes the process (SIGBUS) when the mapping is read,
+    /// so mapping stops as soon as the watcher sees a file modified in place rather than replaced.
+    fn mmap(&self) -> bool {
+        self.emotes_config.mmap && !self.mmap_unsafe.load(Ordering::SeqCst)
+    }
+
+    /// Returns the bytes of an emote, from the payload cache when possible.
+    pub fn payload(&self, emote: &Emote) -> Result<Arc<Payload>> {
+        if emote.bytes.is_some() || self.emotes_config.cache_size == 0 {
+            return Ok(emote.load(self.mmap())?);
+        }
+
+        if let Some(payload) = self.lock_payload_cache()?.get(&emote.path) {
+            return Ok(payload);
+        }
+        let payload = emote.load(self.mmap())?;
+        self.lock_payload_cache()?.put(emote.path.clone(), payload.clone());
+        Ok(payload)
+    }
+
+    /// Whether the file of the emote is in this library, its payload should then be loaded by this manager.
+    pub fn owns(&self, emote: &Emote) -> bool {
+        emote.bytes.is_none() && !emote.path.as_os_str().is_empty() && emote.path.starts_with(&self.assets_directory)
+    }
 
-        let res = self.find_twitch_emote_urls(&name, 1, true)?;
-        Ok(match res.first() {
-            Some(emote) => {
-                let mut res = reqwest::blocking::get(&emote.url)?;
-                let mut bytes: Vec<u8> = Vec::new();
-                res.copy_to(&mut bytes)?;
-                Some(Emote {
-                    path: PathBuf::new(),
-                    file_name: format!("{}.png", name),
-                    name,
-                    bytes,
-                })
+    /// Returns the emote with its bytes loaded through this manager, so that another manager can render it.
+    pub fn loaded(&self, emote: &Arc<Emote>) -> Result<Arc<Emote>> {
+        if emote.bytes.is_some() {
+            return Ok(emote.clone());
+        }
+
+        Ok(Arc::new(Emote {
+            path: emote.path.clone(),
+            file_name: emote.file_name.clone(),
+            name: emote.name.clone(),
+            category: emote.category.clone(),
+            pack: emote.pack.clone(),
+            size: emote.size,
+            meta: emote.meta.clone(),
+            bytes: Some(self.payload(emote)?),
+        }))
+    }
+
+    /// Returns the emote transformed by the modifiers, rendering it only if it is not in the cache yet.
+    pub fn modified(&self, emote: &Arc<Emote>, modifiers: &[Modifier]) -> Result<Arc<Emote>> {
+        if modifiers.is_empty() {
+            return Ok(emote.clone());
+        }
+
+        let key = format!("{}|{}", Self::cache_key(emote), modifiers::key(modifiers));
+        let cached = self.lock_modified_cache()?.get(&key);
+        let payload = match cached {
+            Some(payload) => payload,
+            None => {
+                let (bytes, _format) = modifiers::apply(&self.payload(emote)?, modifiers, self.emotes_config.upload_limit)?;
+                let payload = Arc::new(Payload::Memory(bytes));
+                self.lock_modified_cache()?.put(key, payload.clone());
+                payload
             },
-            None => None,
+        };
+
+        let file_name = media::file_name(&emote.name, &payload);
+        Ok(Arc::new(Emote::from_payload(emote.name.clone(), file_name, payload)))
+    }
+
+    /// Composes several emotes into a single image, rendering it only if it is not in the cache yet.
+    pub fn combined(&self, emotes: &[Arc<Emote>]) -> Result<Arc<Emote>> {
+        if let [emote] = emotes {
+            return Ok(emote.clone());
+        }
+
+        let key = emotes.iter().map(|emote| Self::cache_key(emote)).collect::<Vec<_>>().join("+");
+        let cached = self.lock_modified_cache()?.get(&key);
+        let payload = match cached {
+            Some(payload) => payload,
+            None => {
+                let payloads = emotes.iter().map(|emote| self.payload(emote)).collect::<Result<Vec<_>>>()?;
+                let (bytes, _format) = combine::combine(&payloads.iter().map(|payload| &payload[..]).collect::<Vec<_>>(), self.emotes_config.upload_limit)?;
+                let payload = Arc::new(Payload::Memory(bytes));
+                self.lock_modified_cache()?.put(key, payload.clone());
+                payload
+            },
+        };
+
+        let name = emotes.iter().map(|emote| emote.name.as_str()).collect::<Vec<_>>().join("+");
+        let file_name = media::file_name(&name, &payload);
+        Ok(Arc::new(Emote::from_payload(name, file_name, payload)))
+    }
+
+    /// Identifies an emote in the rendered emotes cache.
+    /// Remote emotes have no path, their name and size tell them apart instead.
+    fn cache_key(emote: &Emote) -> String {
+        format!("{}|{}|{}", emote.path.display(), emote.name, emote.size)
+    }
+
+    /// Finds an emote by its qualified name (`pack/name`), or by its name alone.
+    /// A bare name resolves to the emote outside of any pack, or to the only pack emote with that name.
+    pub fn find_emote_by_name(&self, name: &str) -> Result<Option<Arc<Emote>>> {
+        let name = name.to_lowercase();
+        let emotes = self.read_emotes()?;
+
+        if let Some((pack, name)) = name.rsplit_once('/') {
+            return Ok(emotes.iter().find(|emote| emote.pack.as_deref() == Some(pack) && emote.has_name(name)).cloned());
+        }
+
+        if let Some(emote) = emotes.iter().find(|emote| emote.pack.is_none() && emote.has_name(&name)) {
+            return Ok(Some(emote.clone()));
+        }
+        let mut in_packs = emotes.iter().filter(|emote| emote.has_name(&name));
+        match (in_packs.next(), in_packs.next()) {
+            (Some(emote), None) => Ok(Some(emote.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Ranks the emotes by similarity to `query`, best match first.
+    pub fn search_emotes(&self, query: &str, limit: usize) -> Result<Vec<(Arc<Emote>, f64)>> {
+        Ok(Self::rank(query, &self.read_emotes()?, limit))
+    }
+
+    /// Scores every emote by the best similarity of its name and aliases to `query`.
+    /// Emotes with the same score are sorted by name.
+    fn rank(query: &str, emotes: &[Arc<Emote>], limit: usize) -> Vec<(Arc<Emote>, f64)> {
+        let query = query.to_lowercase();
+        let mut results = emotes
+                            .iter()
+                            .map(|emote| {
+                                let score = emote.meta.aliases
+                                                .iter()
+                                                .map(|alias| Self::similarity(&query, alias))
+                                                .fold(Self::similarity(&query, &emote.name), f64::max);
+                                (emote.clone(), score)
+                            })
+                            .filter(|(_emote, score)| *score >= MIN_SIMILARITY)
+                            .collect::<Vec<_>>();
+
+        results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then_with(|| a.0.name.cmp(&b.0.name)));
+        results.truncate(limit);
+        results
+    }
+
+    /// Returns the closest emote to `name`, only if it is clearly a better match than the other candidates.
+    pub fn autocorrect_emote(&self, name: &str) -> Result<Option<Arc<Emote>>> {
+        Ok(Self::autocorrection(&self.search_emotes(name, 2)?))
+    }
+
+    /// Picks the best of the two best results of a search if it is close enough and ahead of the second one.
+    fn autocorrection(results: &[(Arc<Emote>, f64)]) -> Option<Arc<Emote>> {
+        match results {
+            [(best, score)] if *score >= AUTOCORRECT_SIMILARITY => Some(best.clone()),
+            [(best, score), (_, second)] if *score >= AUTOCORRECT_SIMILARITY && score - second >= AUTOCORRECT_MARGIN => Some(best.clone()),
+            _ => None,
+        }
+    }
+
+    /// Scores how close `name` is to `query` between 0 and 1, taking the best of edit distance and prefix/substring matching.
+    fn similarity(query: &str, name: &str) -> f64 {
+        if query.is_empty() {
+            return 0.0;
+        }
+        if query == name {
+            return 1.0;
+        }
+
+        let coverage = query.len() as f64 / name.len() as f64;
+        let containment = if name.starts_with(query) {
+            0.7 + 0.2 * coverage
+        } else if name.contains(query) {
+            0.6 + 0.2 * coverage
+        } else {
+            0.0
+        };
+        let edit = strsim::normalized_damerau_levenshtein(query, name);
+
+        containment.max(edit)
+    }
+
+    /// Saves the state of the remote sources, errors are only logged since this happens on shutdown.
+    pub fn save_sources(&self) {
+        for (name, source) in self.sources.iter() {
+            if let Err(err) = source.save() {
+                log::error!("Could not save emote source \"{}\": {}", name, err);
+            }
+        }
+    }
+
+    /// Returns the emote source registered under `name`, `"local"` being this manager.
+    pub fn source(&self, name: &str) -> Option<&dyn EmoteSource> {
+        if name == LOCAL_SOURCE {
+            Some(self as &dyn EmoteSource)
+        } else {
+            self.sources.get(name).map(|source| source.as_ref())
+        }
+    }
+
+    /// Largest file that can be sent to Discord, in bytes.
+    pub fn upload_limit(&self) -> u64 {
+        self.emotes_config.upload_limit
+    }
+
+    pub fn assets_directory(&self) -> &Path {
+        &self.assets_directory
+    }
+
+    /// The emote categories, in the order of the configuration.
+    pub fn categories(&self) -> &Vec<CategoryConfig> {
+        &self.emotes_config.categories
+    }
+
+    pub fn category(&self, directory: &str) -> Option<&CategoryConfig> {
+        self.emotes_config.categories.iter().find(|category| category.directory == directory)
+    }
+
+    /// The name conflicts found when the library was last loaded.
+    pub fn conflicts(&self) -> Result<Vec<Conflict>> {
+        Ok(self.conflicts.read().map_err(|_err| Error::new(ErrorKind::ManagerRead))?.clone())
+    }
+
+    /// The assets that were skipped because they cannot be sent.
+    pub fn validation_summary(&self) -> Result<ValidationSummary> {
+        Ok(ValidationSummary {
+            invalid: self.invalid_assets.read().map_err(|_err| Error::new(ErrorKind::ManagerRead))?.clone(),
         })
     }
 
-    pub fn n_emotes(&self) -> usize {
-        self.emotes.len()
+    pub fn n_emotes(&self) -> Result<usize> {
+        Ok(self.read_emotes()?.len())
     }
 
-    pub fn emotes(&self) -> &Vec<Emote> {
-        &self.emotes
+    /// Returns a snapshot of the emote list, which stays valid even if the library is reloaded meanwhile.
+    pub fn emotes(&self) -> Result<Vec<Arc<Emote>>> {
+        Ok(self.read_emotes()?.clone())
     }
 
-    pub fn text_emotes(&self) -> &Vec<(Vec<&'static str>, &'static str)> {
+    /// Returns the built-in text emotes followed by the ones defined in the configuration.
+    pub fn text_emotes(&self) -> &Vec<TextEmote> {
         &self.text_emotes
     }
+
+    /// Merges a user's text emotes with the shared ones into a list of `(trigger, text)` replacements.
+    /// User triggers override shared ones, and longer triggers come first so that `shrug` is not eaten by `s`.
+    pub fn text_emotes_for(&self, user_text_emotes: &[TextEmote]) -> Vec<(String, String)> {
+        let mut replacements: Vec<(String, String)> = Vec::new();
+        for text_emote in user_text_emotes.iter().chain(self.text_emotes.iter().rev()) {
+            for trigger in text_emote.triggers.iter() {
+                if !replacements.iter().any(|(t, _text)| t == trigger) {
+                    replacements.push((trigger.clone(), text_emote.text.clone()));
+                }
+            }
+        }
+
+        replacements.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        replacements
+    }
 }
 
 impl Key for EmoteManager {
     type Value = Arc<EmoteManager>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ self, TempDir };
+
+    // Results of a search, expected correction
+    type CorrectionCase = (Vec<(Arc<Emote>, f64)>, Option<&'static str>);
+
+    /// A library of the default categories with the given files, by path relative to the assets directory.
+    fn library(files: &[(&str, Vec<u8>)]) -> (TempDir, EmoteManager) {
+        let directory = TempDir::new("emotes");
+        for (path, bytes) in files {
+            let path = directory.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, bytes).unwrap();
+        }
+        let mngr = EmoteManager::private(&EmotesConfig::default(), directory.path()).unwrap();
+        (directory, mngr)
+    }
+
+    fn qualified_names(mngr: &EmoteManager) -> Vec<String> {
+        let mut names = mngr.emotes().unwrap().iter().map(|emote| emote.qualified_name()).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Puts the payload of an emote and a modified version of it in the caches.
+    fn fill_caches(mngr: &EmoteManager, name: &str) {
+        let emote = mngr.find_emote_by_name(name).unwrap().unwrap();
+        mngr.payload(&emote).unwrap();
+        mngr.modified(&emote, &[ Modifier::FlipHorizontal ]).unwrap();
+    }
+
+    /// The number of entries of the payload cache and of the modified cache.
+    fn cached(mngr: &EmoteManager) -> (usize, usize) {
+        (mngr.lock_payload_cache().unwrap().entries.len(), mngr.lock_modified_cache().unwrap().entries.len())
+    }
+
+    fn emote(name: &str, aliases: &[&str]) -> Arc<Emote> {
+        let mut emote = Emote::from_bytes(name.to_owned(), format!("{}.png", name), Vec::new());
+        emote.meta.aliases = aliases.iter().map(|alias| (*alias).to_owned()).collect();
+        Arc::new(emote)
+    }
+
+    fn names(results: &[(Arc<Emote>, f64)]) -> Vec<&str> {
+        results.iter().map(|(emote, _score)| emote.name.as_str()).collect()
+    }
+
+    #[test]
+    fn similarity() {
+        // Query, name, minimum score, maximum score
+        let cases: Vec<(&str, &str, f64, f64)> = vec![
+            ("kappa", "kappa", 1.0, 1.0),
+            ("", "kappa", 0.0, 0.0),
+            ("kap", "kappa", 0.7, 0.9), // Prefix
+            ("app", "kappa", 0.6, 0.8), // Substring
+            ("kapap", "kappa", 0.75, 0.85), // Transposition
+            ("xyz", "kappa", 0.0, 0.2),
+            ("kappapride", "kappa", 0.0, 0.5), // The query is longer than the name
+        ];
+
+        for (query, name, min, max) in cases {
+            let score = EmoteManager::similarity(query, name);
+            assert!(score >= min && score <= max, "query: {:?}, name: {:?}, score: {}", query, name, score);
+        }
+        assert!(EmoteManager::similarity("kap", "kappa") > EmoteManager::similarity("app", "kappa"));
+    }
+
+    #[test]
+    fn find_by_name() {
+        let files = [ "emojis/kappa.png", "emojis/frog/kappa.png", "emojis/pepe/sad.png", "emojis/pepe/happy.png", "emojis/frog/sad.png" ];
+        let mut files = files.iter().map(|path| (*path, testing::png(8, 8))).collect::<Vec<_>>();
+        files.push((MANIFEST_FILE, b"[\"pepe/happy\"]\naliases = [\"smile\"]\n".to_vec()));
+        let (_directory, mngr) = library(&files);
+
+        // Name, qualified name of the emote found
+        let cases: Vec<(&str, Option<&str>)> = vec![
+            ("pepe/sad", Some("pepe/sad")), // Qualified with the pack
+            ("frog/sad", Some("frog/sad")),
+            ("PEPE/Sad", Some("pepe/sad")),
+            ("pepe/kappa", None),
+            ("happy", Some("pepe/happy")), // Unique among the packs
+            ("smile", Some("pepe/happy")), // Alias
+            ("kappa", Some("kappa")), // The emote outside of the packs comes first
+            ("sad", None), // In several packs
+            ("pog", None),
+        ];
+
+        for (name, expected) in cases {
+            let found = mngr.find_emote_by_name(name).unwrap().map(|emote| emote.qualified_name());
+            assert_eq!(found.as_deref(), expected, "name: {:?}", name);
+        }
+    }
+
+    #[test]
+    fn rank() {
+        let emotes = vec![ emote("pogo", &[]), emote("pogu", &[]), emote("kappa", &[ "kap" ]), emote("pog", &[]) ];
+        // Query, limit, expected names in order
+        let cases: Vec<(&str, usize, Vec<&str>)> = vec![
+            ("pog", 10, vec![ "pog", "pogo", "pogu" ]), // Same score, sorted by name
+            ("pog", 2, vec![ "pog", "pogo" ]),
+            ("POG", 1, vec![ "pog" ]),
+            ("kap", 10, vec![ "kappa" ]), // The alias matches exactly
+            ("zzz", 10, vec![]),
+        ];
+
+        for (query, limit, expected) in cases {
+            let results = EmoteManager::rank(query, &emotes, limit);
+            assert_eq!(names(&results), expected, "query: {:?}, limit: {}", query, limit);
+        }
+    }
+
+    #[test]
+    fn autocorrection() {
+        let (kappa, pogo) = (emote("kappa", &[]), emote("pogo", &[]));
+        let cases: Vec<CorrectionCase> = vec![
+            (vec![], None),
+            (vec![ (kappa.clone(), 0.8) ], Some("kappa")),
+            (vec![ (kappa.clone(), AUTOCORRECT_SIMILARITY) ], Some("kappa")),
+            (vec![ (kappa.clone(), 0.7) ], None), // Not close enough
+            (vec![ (kappa.clone(), 0.9), (pogo.clone(), 0.75) ], Some("kappa")),
+            (vec![ (kappa.clone(), 0.9), (pogo.clone(), 0.85) ], None), // Ambiguous
+            (vec![ (kappa.clone(), 0.8), (pogo.clone(), 0.8) ], None), // Tie
+        ];
+
+        for (results, expected) in cases {
+            let corrected = EmoteManager::autocorrection(&results);
+            assert_eq!(corrected.as_ref().map(|emote| emote.name.as_str()), expected, "results: {:?}", names(&results));
+        }
+    }
+
+    #[test]
+    fn rename_moves_manifest_entry() {
+        let manifest = b"[kappa]\naliases = [\"kap\"]\ndescription = \"Kappa\"\n".to_vec();
+        let (directory, mngr) = library(&[ ("emojis/kappa.png", testing::png(8, 8)), ("emojis/pog.png", testing::png(8, 8)), (MANIFEST_FILE, manifest) ]);
+
+        let renamed = mngr.rename_emote("kappa", "faces/keepo").unwrap();
+        assert_eq!(renamed.meta.aliases, vec![ "kap" ]);
+        assert_eq!(renamed.meta.description.as_deref(), Some("Kappa"));
+        assert_eq!(mngr.find_emote_by_name("kap").unwrap().map(|emote| emote.qualified_name()), Some("faces/keepo".to_owned()));
+
+        // Emotes without an entry are renamed without touching the manifest
+        mngr.rename_emote("pog", "pogchamp").unwrap();
+        assert!(mngr.find_emote_by_name("pogchamp").unwrap().unwrap().meta.aliases.is_empty());
+
+        let manifest = EmoteManager::load_manifest(directory.path()).unwrap();
+        assert_eq!(manifest.keys().collect::<Vec<_>>(), vec![ "faces/keepo" ]);
+        mngr.reload().unwrap();
+        assert_eq!(mngr.find_emote_by_name("faces/keepo").unwrap().unwrap().meta.aliases, vec![ "kap" ]);
+    }
+
+    #[test]
+    fn watch_events() {
+        let (directory, mngr) = library(&[ ("emojis/kappa.png", testing::png(8, 8)) ]);
+        let emojis = directory.path().join("emojis");
+
+        // Added files and pack directories
+        std::fs::write(emojis.join("pog.png"), testing::png(8, 8)).unwrap();
+        mngr.handle_watch_event(DebouncedEvent::Create(emojis.join("pog.png"))).unwrap();
+        std::fs::create_dir(emojis.join("pepe")).unwrap();
+        std::fs::write(emojis.join("pepe/sad.png"), testing::png(8, 8)).unwrap();
+        mngr.handle_watch_event(DebouncedEvent::Create(emojis.join("pepe"))).unwrap();
+        assert_eq!(qualified_names(&mngr), vec![ "kappa", "pepe/sad", "pog" ]);
+
+        // Replaced files
+        fill_caches(&mngr, "kappa");
+        assert_eq!(cached(&mngr), (1, 1));
+        let replacement = testing::png(16, 16);
+        testing::replace_file(&emojis.join("kappa.png"), &replacement);
+        mngr.handle_watch_event(DebouncedEvent::Rename(emojis.join("kappa.tmp"), emojis.join("kappa.png"))).unwrap();
+        assert_eq!(cached(&mngr), (0, 0));
+        let kappa = mngr.find_emote_by_name("kappa").unwrap().unwrap();
+        assert_eq!(kappa.size, replacement.len() as u64);
+        assert_eq!(&mngr.payload(&kappa).unwrap()[..], &replacement[..]);
+
+        // Removed files and pack directories
+        fill_caches(&mngr, "pog");
+        std::fs::remove_file(emojis.join("pog.png")).unwrap();
+        mngr.handle_watch_event(DebouncedEvent::Remove(emojis.join("pog.png"))).unwrap();
+        assert_eq!(cached(&mngr), (1, 0)); // Only kappa is left in the payload cache
+        std::fs::remove_dir_all(emojis.join("pepe")).unwrap();
+        mngr.handle_watch_event(DebouncedEvent::Remove(emojis.join("pepe"))).unwrap();
+        assert_eq!(qualified_names(&mngr), vec![ "kappa" ]);
+    }
+
+    #[test]
+    fn watch_conflicts() {
+        let (directory, mngr) = library(&[ ("emojis/kappa.png", testing::png(8, 8)) ]);
+        let emoji = directory.path().join("emojis/kappa.png");
+        let gif = directory.path().join("gifs/kappa.png");
+
+        // The library is reloaded and the emote of the first category keeps the name
+        fill_caches(&mngr, "kappa");
+        std::fs::write(&gif, testing::png(16, 16)).unwrap();
+        mngr.handle_watch_event(DebouncedEvent::Create(gif.clone())).unwrap();
+        assert_eq!(cached(&mngr), (0, 0));
+        assert_eq!(mngr.conflicts().unwrap().len(), 1);
+        assert_eq!(mngr.emotes().unwrap().iter().map(|emote| emote.path.clone()).collect::<Vec<_>>(), vec![ emoji.clone() ]);
+
+        // The other emote gets the name back once the conflict is gone
+        std::fs::remove_file(&emoji).unwrap();
+        mngr.handle_watch_event(DebouncedEvent::Remove(emoji)).unwrap();
+        assert!(mngr.conflicts().unwrap().is_empty());
+        assert_eq!(mngr.emotes().unwrap().iter().map(|emote| emote.path.clone()).collect::<Vec<_>>(), vec![ gif ]);
+    }
+}
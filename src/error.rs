@@ -22,6 +22,13 @@ pub enum ErrorKind {
     Serde,
     Reqwest,
     TwitchEmotes,
+    Watcher,
+    Manifest,
+    TwitchCache,
+    Image,
+    EmoteConflict,
+    Stats,
+    EditLibrary,
 }
 
 #[derive(Debug, Clone)]
@@ -60,6 +67,10 @@ impl Error {
         }
     }
 
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
     pub fn custom(message: &str) -> Self {
         Self {
             kind: ErrorKind::Other,
@@ -82,6 +93,13 @@ impl Error {
             ErrorKind::Serde => "could not serialize/deserialize JSON",
             ErrorKind::Reqwest => "reqwest error",
             ErrorKind::TwitchEmotes => "Twitch API error while loading emote data",
+            ErrorKind::Watcher => "could not watch emote directories",
+            ErrorKind::Manifest => "could not load emote manifest",
+            ErrorKind::TwitchCache => "could not access the Twitch emote cache",
+            ErrorKind::Image => "could not process image",
+            ErrorKind::EmoteConflict => "several emotes have the same name",
+            ErrorKind::Stats => "could not access the emote usage statistics",
+            ErrorKind::EditLibrary => "could not edit the emote library",
         }.into()
     }
 }
@@ -135,3 +153,15 @@ impl From<config::ConfigError> for Error {
         Self::from(ErrorKind::Config, err)
     }
 }
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Self::from(ErrorKind::Image, err)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(err: notify::Error) -> Self {
+        Self::from(ErrorKind::Watcher, err)
+    }
+}
@@ -7,29 +7,57 @@ pub mod tools;
 pub mod error;
 pub mod config;
 pub mod commands;
+pub mod transformers;
+pub mod sources;
 pub mod emote_manager;
+pub mod media;
+pub mod combine;
+pub mod modifiers;
+pub mod twitch_cache;
+pub mod conflicts;
+pub mod validation;
+pub mod stats;
+#[cfg(test)]
+mod testing;
 
 use std::{
     thread,
-    path::PathBuf,
+    collections::HashMap,
     sync::{
         Arc,
         atomic::{ AtomicBool, Ordering },
     },
 };
 
-use crate::config::Config;
+use crate::{
+    bot::User,
+    config::Config,
+    stats::UsageStats,
+};
 pub use emote_manager::EmoteManager;
 pub use error::{ Error, ErrorKind, Result };
 
 fn load_emotes(config: &Config) -> Result<EmoteManager> {
     log::info!("Loading emotes...");
-    let emotes_path = PathBuf::from("assets");
-    let mngr = EmoteManager::new(config, &emotes_path)?;
-    log::info!("Loaded {} emote assets.", mngr.n_emotes());
+    let mngr = EmoteManager::new(config)?;
+    log::info!("Loaded {} emote assets, {}.", mngr.n_emotes()?, mngr.validation_summary()?);
     Ok(mngr)
 }
 
+/// Loads and watches the private emotes of the users that have some, by Discord id.
+fn load_private_emotes(config: &Config, users: &[User]) -> Result<HashMap<u64, Arc<EmoteManager>>> {
+    let mut private_emotes = HashMap::new();
+    for user in users {
+        if let Some(directory) = &user.private_emotes {
+            let mngr = Arc::new(EmoteManager::private(&config.emotes, directory)?);
+            log::info!("Loaded {} private emote assets for user {}, {}.", mngr.n_emotes()?, user.discord_id, mngr.validation_summary()?);
+            EmoteManager::watch(mngr.clone())?;
+            private_emotes.insert(user.discord_id, mngr);
+        }
+    }
+    Ok(private_emotes)
+}
+
 fn setup_ctrl_c(run: Arc<AtomicBool>) -> Result<()> {
     ctrlc::set_handler(move || {
         run.store(false, Ordering::SeqCst);
@@ -72,10 +100,10 @@ fn setup_heroku_wakeup_thread(url: String) {
     }
 }
 
-fn start_www(config: Arc<Config>, emote_mngr: Arc<EmoteManager>) {
+fn start_www(config: Arc<Config>, users: Vec<User>, emote_mngr: Arc<EmoteManager>, private_emotes: HashMap<u64, Arc<EmoteManager>>, stats: Arc<UsageStats>) {
     thread::spawn(move || {
         log::info!("Starting web server...");
-        let res = www::start(&config.www, emote_mngr);
+        let res = www::start(&config.www, users, config.emotes.editors.clone(), emote_mngr, private_emotes, stats);
         if let Err(err) = res {
             log::error!("Web server error: {}", err);
         }
@@ -98,6 +126,9 @@ fn main() -> Result<()> {
         if arg == "--print-config" {
             return tools::print_config::run(&config);
         }
+        if arg == "--conflicts" {
+            return tools::conflicts::run(&config);
+        }
     }
 
     if config.www.enabled {
@@ -109,22 +140,27 @@ fn main() -> Result<()> {
                         .filter(|user| user.active)
                         .collect::<Vec<_>>();
     let emote_mngr = Arc::new(load_emotes(&config)?);
+    EmoteManager::watch(emote_mngr.clone())?;
+    let private_emotes = load_private_emotes(&config, &users)?;
+    let stats = Arc::new(UsageStats::new(&config.stats)?);
     let config = Arc::new(config);
 
     log::info!("Starting {} bot{}...", users.len(), if users.len() > 1 { "s" } else { "" });
-    for user in users {
+    for user in users.iter().cloned() {
         let config = config.clone();
         let emote_mngr = emote_mngr.clone();
+        let user_private_emotes = private_emotes.get(&user.discord_id).cloned();
+        let stats = stats.clone();
         thread::spawn(move || {
             let user_id = user.discord_id;
-            if let Err(err) = bot::Bot::start(user, config, emote_mngr) {
+            if let Err(err) = bot::Bot::start(user, config, emote_mngr, user_private_emotes, stats) {
                 log::error!("Error while starting bot for user {}: {}", user_id, err);
             }
         });
     }
 
     if config.www.enabled {
-        start_www(config, emote_mngr);
+        start_www(config, users, emote_mngr.clone(), private_emotes, stats.clone());
     }
 
     let run = Arc::new(AtomicBool::new(true));
@@ -132,5 +168,9 @@ fn main() -> Result<()> {
     wait_loop(run);
 
     log::info!("Shutting down.");
+    if let Err(err) = stats.save() {
+        log::error!("Could not save the emote usage statistics: {}", err);
+    }
+    emote_mngr.save_sources();
     Ok(())
 }
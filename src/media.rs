@@ -0,0 +1,432 @@
+use std::io::Cursor;
+
+use crate::{ Error, ErrorKind, Result };
+
+use image::{
+    Frame, AnimationDecoder,
+    imageops::{ self, FilterType },
+    codecs::{
+        png::PngDecoder,
+        gif::{ GifEncoder, Repeat },
+    },
+};
+
+/// Browsers and Discord play GIF frames shorter than this one much slower, at 100ms.
+pub const MIN_FRAME_DELAY_MS: f64 = 20.0;
+/// Number of times a GIF is scaled down to fit in the upload limit before giving up.
+const MAX_DOWNSCALES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaFormat {
+    Png,
+    Apng,
+    Gif,
+    Jpeg,
+    Webp,
+    AnimatedWebp,
+    Mp3,
+    Ogg,
+    Wav,
+    Unknown,
+}
+
+impl MediaFormat {
+    /// Detects the format from the first bytes of the file.
+    pub fn sniff(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            if Self::png_has_animation(bytes) {
+                MediaFormat::Apng
+            } else {
+                MediaFormat::Png
+            }
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            MediaFormat::Gif
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            MediaFormat::Jpeg
+        } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            // The animation flag is the second bit of the extended format (VP8X) header
+            if bytes.len() >= 21 && &bytes[12..16] == b"VP8X" && bytes[20] & 0x02 != 0 {
+                MediaFormat::AnimatedWebp
+            } else {
+                MediaFormat::Webp
+            }
+        } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+            MediaFormat::Wav
+        } else if bytes.starts_with(b"OggS") {
+            MediaFormat::Ogg
+        } else if bytes.starts_with(b"ID3") || (bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0) {
+            MediaFormat::Mp3
+        } else {
+            MediaFormat::Unknown
+        }
+    }
+
+    /// An APNG has an animation control chunk before its first image data chunk.
+    fn png_has_animation(bytes: &[u8]) -> bool {
+        let mut offset = 8;
+        while offset + 8 <= bytes.len() {
+            let len = u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]) as usize;
+            match &bytes[offset + 4..offset + 8] {
+                b"acTL" => return true,
+                b"IDAT" => return false,
+                _ => {},
+            };
+            offset = offset.saturating_add(12).saturating_add(len); // Length, type, data and CRC, the length may be garbage
+        }
+        false
+    }
+
+    pub fn from_content_type(content_type: &str) -> Self {
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+        match mime.as_str() {
+            "image/png" => MediaFormat::Png,
+            "image/apng" => MediaFormat::Apng,
+            "image/gif" => MediaFormat::Gif,
+            "image/jpeg" => MediaFormat::Jpeg,
+            "image/webp" => MediaFormat::Webp,
+            "audio/mpeg" => MediaFormat::Mp3,
+            "audio/ogg" => MediaFormat::Ogg,
+            "audio/wav" | "audio/x-wav" => MediaFormat::Wav,
+            _ => MediaFormat::Unknown,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            MediaFormat::Png | MediaFormat::Apng | MediaFormat::Unknown => "png",
+            MediaFormat::Gif => "gif",
+            MediaFormat::Jpeg => "jpg",
+            MediaFormat::Webp | MediaFormat::AnimatedWebp => "webp",
+            MediaFormat::Mp3 => "mp3",
+            MediaFormat::Ogg => "ogg",
+            MediaFormat::Wav => "wav",
+        }
+    }
+
+    pub fn is_image(self) -> bool {
+        matches!(self, MediaFormat::Png | MediaFormat::Apng | MediaFormat::Gif | MediaFormat::Jpeg | MediaFormat::Webp | MediaFormat::AnimatedWebp)
+    }
+
+    pub fn is_audio(self) -> bool {
+        matches!(self, MediaFormat::Mp3 | MediaFormat::Ogg | MediaFormat::Wav)
+    }
+}
+
+/// Returns the duration of a sound in seconds from its headers, without decoding it.
+/// `None` if the headers cannot be read, the MP3 duration is estimated from the bitrate of the first frame.
+pub fn audio_duration(bytes: &[u8]) -> Option<f64> {
+    match MediaFormat::sniff(bytes) {
+        MediaFormat::Wav => wav_duration(bytes),
+        MediaFormat::Ogg => ogg_duration(bytes),
+        MediaFormat::Mp3 => mp3_duration(bytes),
+        _ => None,
+    }
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The data chunk size divided by the byte rate of the format chunk.
+fn wav_duration(bytes: &[u8]) -> Option<f64> {
+    let mut byte_rate = None;
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let len = u32_le(bytes, offset + 4)? as usize;
+        match &bytes[offset..offset + 4] {
+            b"fmt " => byte_rate = u32_le(bytes, offset + 16).filter(|rate| *rate > 0),
+            b"data" => {
+                // Streamed files have a placeholder length, the data goes until the end
+                let len = len.min(bytes.len() - offset - 8);
+                return Some(len as f64 / byte_rate? as f64);
+            },
+            _ => {},
+        };
+        offset = offset.saturating_add(8).saturating_add(len + len % 2); // Chunks are padded to an even length
+    }
+    None
+}
+
+/// The granule position of the last page divided by the sample rate of the identification header.
+fn ogg_duration(bytes: &[u8]) -> Option<f64> {
+    let segments = *bytes.get(26)? as usize;
+    let packet = bytes.get(27 + segments..)?;
+    let sample_rate = if packet.starts_with(b"\x01vorbis") {
+        u32_le(packet, 12).filter(|rate| *rate > 0)?
+    } else if packet.starts_with(b"OpusHead") {
+        48_000 // Opus granule positions are always counted at 48kHz
+    } else {
+        return None;
+    };
+
+    // Pages that do not end a packet have a granule position of -1
+    let granule = (0..bytes.len().saturating_sub(14))
+                    .rev()
+                    .filter(|offset| &bytes[*offset..offset + 4] == b"OggS")
+                    .map(|offset| {
+                        let mut granule = [0; 8];
+                        granule.copy_from_slice(&bytes[offset + 6..offset + 14]);
+                        i64::from_le_bytes(granule)
+                    })
+                    .find(|granule| *granule >= 0)?;
+    Some(granule as f64 / sample_rate as f64)
+}
+
+/// The size of the frames divided by the bitrate of the first MPEG layer III frame.
+fn mp3_duration(bytes: &[u8]) -> Option<f64> {
+    const MPEG1_BITRATES: [u32; 15] = [ 0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320 ];
+    const MPEG2_BITRATES: [u32; 15] = [ 0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160 ];
+
+    // The ID3v2 tag size is stored on 7 bits per byte
+    let mut offset = 0;
+    if bytes.starts_with(b"ID3") {
+        let size = bytes.get(6..10)?.iter().fold(0, |size, byte| (size << 7) | (*byte as usize & 0x7F));
+        offset = 10 + size;
+    }
+
+    let frames = bytes.get(offset..)?;
+    let start = frames.windows(3).position(|header| header[0] == 0xFF && header[1] & 0xE0 == 0xE0 && header[1] & 0x06 == 0x02)?;
+    let header = &frames[start..start + 3];
+    let bitrates = match (header[1] >> 3) & 0x03 {
+        0b11 => &MPEG1_BITRATES,
+        0b10 | 0b00 => &MPEG2_BITRATES,
+        _ => return None, // Reserved version
+    };
+    let bitrate = *bitrates.get((header[2] >> 4) as usize).filter(|bitrate| **bitrate > 0)?;
+    if (header[2] >> 2) & 0x03 == 0x03 {
+        return None; // Reserved sample rate
+    }
+    Some((frames.len() - start) as f64 * 8.0 / (bitrate as f64 * 1000.0))
+}
+
+/// Detects the format of a downloaded emote, falling back to the `Content-Type` header when the bytes are not recognized,
+/// and converts animations that Discord would not play into GIFs.
+pub fn normalize(bytes: Vec<u8>, content_type: Option<&str>) -> Result<(Vec<u8>, MediaFormat)> {
+    let format = match MediaFormat::sniff(&bytes) {
+        MediaFormat::Unknown => content_type.map_or(MediaFormat::Unknown, MediaFormat::from_content_type),
+        format => format,
+    };
+
+    match format {
+        MediaFormat::Apng => Ok((apng_to_gif(&bytes)?, MediaFormat::Gif)),
+        MediaFormat::AnimatedWebp => {
+            // The image decoder cannot read animated WebP, it is sent as is
+            log::warn!("Animated WebP emotes cannot be converted, they will not be animated");
+            Ok((bytes, format))
+        },
+        _ => Ok((bytes, format)),
+    }
+}
+
+/// Builds the attachment file name of an emote from the format of its bytes.
+pub fn file_name(name: &str, bytes: &[u8]) -> String {
+    format!("{}.{}", name, MediaFormat::sniff(bytes).extension())
+}
+
+fn apng_to_gif(bytes: &[u8]) -> Result<Vec<u8>> {
+    let frames = PngDecoder::new(Cursor::new(bytes))?
+                    .apng()
+                    .into_frames()
+                    .collect_frames()?;
+    encode_gif(frames)
+}
+
+/// Encodes frames into a GIF that loops forever.
+pub fn encode_gif<F>(frames: F) -> Result<Vec<u8>>
+where F: IntoIterator<Item = Frame> {
+    encode_gif_with_repeat(frames, Repeat::Infinite)
+}
+
+/// Encodes frames into a GIF, `Repeat::Finite(n)` plays the animation `n` more times after the first one.
+pub fn encode_gif_with_repeat<F>(frames: F, repeat: Repeat) -> Result<Vec<u8>>
+where F: IntoIterator<Item = Frame> {
+    let mut gif = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif);
+        encoder.set_repeat(repeat)?;
+        encoder.encode_frames(frames)?;
+    }
+    Ok(gif)
+}
+
+/// Encodes frames into a GIF of at most `max_size` bytes, scaling the frames down until it fits.
+pub fn encode_gif_within(mut frames: Vec<Frame>, repeat: Repeat, max_size: u64) -> Result<Vec<u8>> {
+    for _ in 0..=MAX_DOWNSCALES {
+        let gif = encode_gif_with_repeat(frames.iter().cloned(), repeat)?;
+        if gif.len() as u64 <= max_size {
+            return Ok(gif);
+        }
+
+        frames = frames.into_iter().map(|frame| {
+            let delay = frame.delay();
+            let buffer = frame.into_buffer();
+            let width = (buffer.width() * 3 / 4).max(1);
+            let height = (buffer.height() * 3 / 4).max(1);
+            Frame::from_parts(imageops::resize(&buffer, width, height, FilterType::Triangle), 0, 0, delay)
+        }).collect();
+    }
+    Err(Error::with_message(ErrorKind::Image, format!("the GIF does not fit in the upload limit of {} bytes", max_size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    /// A PNG made of chunks with the given types and data lengths, the data and CRCs are zeroes.
+    fn png(chunks: &[(&[u8; 4], u32)]) -> Vec<u8> {
+        let mut bytes = PNG_SIGNATURE.to_vec();
+        for (kind, len) in chunks {
+            bytes.extend_from_slice(&len.to_be_bytes());
+            bytes.extend_from_slice(*kind);
+            bytes.resize(bytes.len() + *len as usize + 4, 0);
+        }
+        bytes
+    }
+
+    fn webp(chunk: &[u8], flags: u8) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WEBP".to_vec();
+        bytes.extend_from_slice(chunk);
+        bytes.extend_from_slice(&[0, 0, 0, 0, flags]);
+        bytes
+    }
+
+    #[test]
+    fn sniff() {
+        let mut truncated_chunk = png(&[ (b"IHDR", 13) ]);
+        truncated_chunk.extend_from_slice(b"\0\0\0\x08ac"); // Cut in the middle of the chunk type
+        let mut garbage_length = PNG_SIGNATURE.to_vec();
+        garbage_length.extend_from_slice(b"\xff\xff\xff\xffIHDR");
+
+        let cases: Vec<(&str, Vec<u8>, MediaFormat)> = vec![
+            // PNG and APNG
+            ("png", png(&[ (b"IHDR", 13), (b"IDAT", 10), (b"IEND", 0) ]), MediaFormat::Png),
+            ("apng", png(&[ (b"IHDR", 13), (b"acTL", 8), (b"IDAT", 10), (b"IEND", 0) ]), MediaFormat::Apng),
+            ("acTL after IDAT", png(&[ (b"IHDR", 13), (b"IDAT", 10), (b"acTL", 8), (b"IEND", 0) ]), MediaFormat::Png),
+            ("signature only", PNG_SIGNATURE.to_vec(), MediaFormat::Png),
+            ("truncated chunk header", truncated_chunk, MediaFormat::Png),
+            ("garbage chunk length", garbage_length, MediaFormat::Png),
+            ("truncated signature", PNG_SIGNATURE[..4].to_vec(), MediaFormat::Unknown),
+            // Other images
+            ("gif87a", b"GIF87a...".to_vec(), MediaFormat::Gif),
+            ("gif89a", b"GIF89a...".to_vec(), MediaFormat::Gif),
+            ("truncated gif", b"GIF8".to_vec(), MediaFormat::Unknown),
+            ("jpeg", vec![ 0xFF, 0xD8, 0xFF, 0xE0 ], MediaFormat::Jpeg),
+            ("webp", webp(b"VP8 ", 0), MediaFormat::Webp),
+            ("extended webp", webp(b"VP8X", 0), MediaFormat::Webp),
+            ("animated webp", webp(b"VP8X", 0x02), MediaFormat::AnimatedWebp),
+            ("truncated extended webp", b"RIFF\0\0\0\0WEBPVP8X".to_vec(), MediaFormat::Webp),
+            ("truncated riff", b"RIFF\0\0\0\0WE".to_vec(), MediaFormat::Unknown),
+            // Audio
+            ("wav", b"RIFF\0\0\0\0WAVEfmt ".to_vec(), MediaFormat::Wav),
+            ("ogg", b"OggS\0".to_vec(), MediaFormat::Ogg),
+            ("mp3 with tag", b"ID3\x04".to_vec(), MediaFormat::Mp3),
+            ("mp3 frame", vec![ 0xFF, 0xFB, 0x90 ], MediaFormat::Mp3),
+            ("single sync byte", vec![ 0xFF ], MediaFormat::Unknown),
+            // Nothing known
+            ("empty", Vec::new(), MediaFormat::Unknown),
+            ("text", b"hello".to_vec(), MediaFormat::Unknown),
+        ];
+
+        for (description, bytes, expected) in cases {
+            assert_eq!(MediaFormat::sniff(&bytes), expected, "{}", description);
+        }
+    }
+
+    fn wav(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (kind, data) in chunks {
+            bytes.extend_from_slice(*kind);
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                bytes.push(0);
+            }
+        }
+        bytes
+    }
+
+    /// The format chunk of 16 bits mono PCM at 8kHz, 16000 bytes per second.
+    fn wav_format() -> Vec<u8> {
+        let mut format = vec![ 1, 0, 1, 0 ]; // PCM, mono
+        format.extend_from_slice(&8000u32.to_le_bytes());
+        format.extend_from_slice(&16000u32.to_le_bytes());
+        format.extend_from_slice(&[ 2, 0, 16, 0 ]);
+        format
+    }
+
+    /// An Ogg page with a single packet.
+    fn ogg_page(granule: i64, packet: &[u8]) -> Vec<u8> {
+        let mut bytes = b"OggS\0\0".to_vec();
+        bytes.extend_from_slice(&granule.to_le_bytes());
+        bytes.resize(26, 0); // Serial number, sequence number and CRC
+        bytes.extend_from_slice(&[ 1, packet.len() as u8 ]);
+        bytes.extend_from_slice(packet);
+        bytes
+    }
+
+    fn vorbis_header(sample_rate: u32) -> Vec<u8> {
+        let mut packet = b"\x01vorbis\0\0\0\0\x02".to_vec();
+        packet.extend_from_slice(&sample_rate.to_le_bytes());
+        packet
+    }
+
+    /// An MPEG 1 layer III frame at 128kbps and 44.1kHz, followed by `len` bytes.
+    fn mp3(len: usize) -> Vec<u8> {
+        let mut bytes = vec![ 0xFF, 0xFB, 0x90, 0x00 ];
+        bytes.resize(bytes.len() + len, 0);
+        bytes
+    }
+
+    #[test]
+    fn audio_duration() {
+        let ogg = |pages: &[Vec<u8>]| pages.concat();
+        let cases: Vec<(&str, Vec<u8>, Option<f64>)> = vec![
+            // WAV
+            ("wav", wav(&[ (b"fmt ", &wav_format()), (b"data", &[ 0; 8000 ]) ]), Some(0.5)),
+            ("wav with odd chunk", wav(&[ (b"fmt ", &wav_format()), (b"LIST", &[ 0; 3 ]), (b"data", &[ 0; 4000 ]) ]), Some(0.25)),
+            ("wav without data", wav(&[ (b"fmt ", &wav_format()) ]), None),
+            ("wav without format", wav(&[ (b"data", &[ 0; 8000 ]) ]), None),
+            ("wav with zero byte rate", wav(&[ (b"fmt ", &[ 0; 16 ]), (b"data", &[ 0; 8000 ]) ]), None),
+            ("truncated wav", b"RIFF\0\0\0\0WAVEfmt ".to_vec(), None),
+            // Ogg
+            ("vorbis", ogg(&[ ogg_page(0, &vorbis_header(44100)), ogg_page(88200, b"audio"), ogg_page(-1, b"audio") ]), Some(2.0)),
+            ("opus", ogg(&[ ogg_page(0, b"OpusHead\x01\x02"), ogg_page(24000, b"audio") ]), Some(0.5)),
+            ("unknown ogg codec", ogg(&[ ogg_page(0, b"\x80theora"), ogg_page(24000, b"video") ]), None),
+            ("vorbis with zero sample rate", ogg(&[ ogg_page(0, &vorbis_header(0)), ogg_page(24000, b"audio") ]), None),
+            ("truncated ogg", b"OggS\0".to_vec(), None),
+            // MP3
+            ("mp3", mp3(15996), Some(1.0)),
+            ("mp3 with tag", [ b"ID3\x04\0\0\0\0\x01\x00".to_vec(), vec![ 0; 128 ], mp3(15996) ].concat(), Some(1.0)),
+            ("mp3 with garbage tag size", [ b"ID3\x04\0\0\x7f\x7f\x7f\x7f".to_vec(), mp3(100) ].concat(), None),
+            ("mp3 with free bitrate", vec![ 0xFF, 0xFB, 0x00, 0x00 ], None),
+            ("mp3 with reserved sample rate", vec![ 0xFF, 0xFB, 0x9C, 0x00 ], None),
+            ("mp3 tag only", b"ID3\x04\0\0\0\0\0\0".to_vec(), None),
+            // Not audio
+            ("png", png(&[ (b"IHDR", 13) ]), None),
+            ("empty", Vec::new(), None),
+        ];
+
+        for (description, bytes, expected) in cases {
+            assert_eq!(super::audio_duration(&bytes), expected, "{}", description);
+        }
+    }
+
+    #[test]
+    fn content_type() {
+        let cases: Vec<(&str, MediaFormat)> = vec![
+            ("image/png", MediaFormat::Png),
+            ("IMAGE/GIF", MediaFormat::Gif),
+            ("image/jpeg; charset=binary", MediaFormat::Jpeg),
+            ("audio/x-wav", MediaFormat::Wav),
+            ("application/octet-stream", MediaFormat::Unknown),
+            ("", MediaFormat::Unknown),
+        ];
+
+        for (content_type, expected) in cases {
+            assert_eq!(MediaFormat::from_content_type(content_type), expected, "{:?}", content_type);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::io::Cursor;
+
+use crate::{
+    media::{ self, MediaFormat },
+    Error, ErrorKind, Result,
+};
+
+use image::{
+    Delay, Frame, DynamicImage, GenericImageView, ImageOutputFormat, AnimationDecoder,
+    imageops::FilterType,
+    codecs::gif::{ GifDecoder, Repeat },
+};
+
+/// Matches the modifiers that can follow an emote name, e.g. `:2x:flip`.
+pub const PATTERN: &str = r"(?i::(?:[1-4]x|half|flipv?|rotate(?:90|180|270)|gr[ae]y(?:scale)?|fast(?:er)?|slow(?:er)?|reverse|once|loop[1-9]))*";
+
+/// Largest width or height an emote can be scaled up to.
+const MAX_SIZE: u32 = 512;
+/// Largest scale factor, as allowed by `PATTERN`.
+const MAX_SCALE: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Modifier {
+    Scale(u32, u32), // Numerator, denominator
+    FlipHorizontal,
+    FlipVertical,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Grayscale,
+    // Only change animated GIFs
+    Speed(u32, u32), // Numerator, denominator
+    Reverse,
+    Loop(u16), // Number of times the animation is played
+}
+
+impl Modifier {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.to_lowercase();
+        Some(match s.as_str() {
+            "half" => Modifier::Scale(1, 2),
+            "flip" => Modifier::FlipHorizontal,
+            "flipv" => Modifier::FlipVertical,
+            "rotate90" => Modifier::Rotate90,
+            "rotate180" => Modifier::Rotate180,
+            "rotate270" => Modifier::Rotate270,
+            "gray" | "grey" | "grayscale" | "greyscale" => Modifier::Grayscale,
+            "fast" => Modifier::Speed(2, 1),
+            "faster" => Modifier::Speed(4, 1),
+            "slow" => Modifier::Speed(1, 2),
+            "slower" => Modifier::Speed(1, 4),
+            "reverse" => Modifier::Reverse,
+            "once" => Modifier::Loop(1),
+            _ => match s.strip_prefix("loop") {
+                Some(count) => Modifier::Loop(count.parse().ok().filter(|count| *count > 0)?),
+                None => Modifier::Scale(s.strip_suffix('x')?.parse().ok().filter(|factor| (1..=MAX_SCALE).contains(factor))?, 1),
+            },
+        })
+    }
+
+    /// Canonical name of the modifier, used to build cache keys.
+    pub fn name(&self) -> String {
+        match self {
+            Modifier::Scale(1, 2) => "half".into(),
+            Modifier::Scale(numerator, denominator) => format!("{}/{}x", numerator, denominator),
+            Modifier::FlipHorizontal => "flip".into(),
+            Modifier::FlipVertical => "flipv".into(),
+            Modifier::Rotate90 => "rotate90".into(),
+            Modifier::Rotate180 => "rotate180".into(),
+            Modifier::Rotate270 => "rotate270".into(),
+            Modifier::Grayscale => "gray".into(),
+            Modifier::Speed(numerator, denominator) => format!("speed{}/{}", numerator, denominator),
+            Modifier::Reverse => "reverse".into(),
+            Modifier::Loop(count) => format!("loop{}", count),
+        }
+    }
+
+    fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match self {
+            Modifier::Scale(numerator, denominator) => {
+                let width = (image.width() * numerator / denominator).clamp(1, MAX_SIZE);
+                let height = (image.height() * numerator / denominator).clamp(1, MAX_SIZE);
+                // Keeps the aspect ratio, fitting in the bounds if the size had to be clamped
+                image.resize(width, height, FilterType::CatmullRom)
+            },
+            Modifier::FlipHorizontal => image.fliph(),
+            Modifier::FlipVertical => image.flipv(),
+            Modifier::Rotate90 => image.rotate90(),
+            Modifier::Rotate180 => image.rotate180(),
+            Modifier::Rotate270 => image.rotate270(),
+            Modifier::Grayscale => image.grayscale(),
+            Modifier::Speed(..) | Modifier::Reverse | Modifier::Loop(_) => image,
+        }
+    }
+}
+
+/// Parses a list of modifiers such as `:2x:flip`, ignoring the unknown ones.
+pub fn parse(modifiers: &str) -> Vec<Modifier> {
+    modifiers.split(':').filter_map(Modifier::parse).collect()
+}
+
+pub fn key(modifiers: &[Modifier]) -> String {
+    modifiers.iter().map(Modifier::name).collect::<Vec<_>>().join(":")
+}
+
+fn apply_to_image(image: DynamicImage, modifiers: &[Modifier]) -> DynamicImage {
+    modifiers.iter().fold(image, |image, modifier| modifier.apply(image))
+}
+
+/// Changes the playback speed of an animation.
+/// Frames that would become shorter than the minimum delay are dropped and their time given to the next frame kept.
+fn retime(frames: Vec<Frame>, numerator: u32, denominator: u32) -> Vec<Frame> {
+    let n_frames = frames.len();
+    let mut retimed = Vec::with_capacity(n_frames);
+    let mut pending = 0.0;
+    for (idx, frame) in frames.into_iter().enumerate() {
+        let (delay_numerator, delay_denominator) = frame.delay().numer_denom_ms();
+        pending += delay_numerator as f64 / delay_denominator.max(1) as f64 * denominator as f64 / numerator as f64;
+        if pending >= media::MIN_FRAME_DELAY_MS || idx + 1 == n_frames {
+            let delay = Delay::from_numer_denom_ms(pending.max(media::MIN_FRAME_DELAY_MS).round() as u32, 1);
+            retimed.push(Frame::from_parts(frame.into_buffer(), 0, 0, delay));
+            pending = 0.0;
+        }
+    }
+    retimed
+}
+
+/// Applies the modifiers to an image, frame by frame for GIFs.
+/// Static images are re-encoded as PNG and ignore the animation modifiers.
+/// GIFs are scaled down if needed to stay under `max_size` bytes.
+pub fn apply(bytes: &[u8], modifiers: &[Modifier], max_size: u64) -> Result<(Vec<u8>, MediaFormat)> {
+    match MediaFormat::sniff(bytes) {
+        MediaFormat::Gif => {
+            let frames = GifDecoder::new(Cursor::new(bytes))?.into_frames().collect_frames()?;
+            let mut frames = frames.into_iter().map(|frame| {
+                let delay = frame.delay();
+                let image = apply_to_image(DynamicImage::ImageRgba8(frame.into_buffer()), modifiers);
+                Frame::from_parts(image.to_rgba8(), 0, 0, delay)
+            }).collect::<Vec<_>>();
+
+            let mut repeat = Repeat::Infinite;
+            for modifier in modifiers.iter() {
+                match modifier {
+                    Modifier::Speed(numerator, denominator) => frames = retime(frames, *numerator, *denominator),
+                    Modifier::Reverse => frames.reverse(),
+                    Modifier::Loop(count) => repeat = Repeat::Finite(count - 1),
+                    _ => {},
+                };
+            }
+            Ok((media::encode_gif_within(frames, repeat, max_size)?, MediaFormat::Gif))
+        },
+        format if format.is_image() => {
+            let image = apply_to_image(image::load_from_memory(bytes)?, modifiers);
+            let mut png = Vec::new();
+            image.write_to(&mut png, ImageOutputFormat::Png)?;
+            if png.len() as u64 > max_size {
+                return Err(Error::with_message(ErrorKind::Image, format!("the image does not fit in the upload limit of {} bytes", max_size)));
+            }
+            Ok((png, MediaFormat::Png))
+        },
+        _ => Err(Error::with_message(ErrorKind::Image, "modifiers can only be applied to images".into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::RgbaImage;
+
+    #[test]
+    fn parse_modifier() {
+        let cases: Vec<(&str, Option<Modifier>)> = vec![
+            ("2x", Some(Modifier::Scale(2, 1))),
+            ("4X", Some(Modifier::Scale(4, 1))),
+            ("half", Some(Modifier::Scale(1, 2))),
+            ("FLIP", Some(Modifier::FlipHorizontal)),
+            ("flipv", Some(Modifier::FlipVertical)),
+            ("rotate270", Some(Modifier::Rotate270)),
+            ("greyscale", Some(Modifier::Grayscale)),
+            ("slower", Some(Modifier::Speed(1, 4))),
+            ("once", Some(Modifier::Loop(1))),
+            ("loop3", Some(Modifier::Loop(3))),
+            // Malformed arguments
+            ("0x", None),
+            ("5x", None),
+            ("-1x", None),
+            ("1.5x", None),
+            ("99999999999x", None),
+            ("x", None),
+            ("2", None),
+            ("loop", None),
+            ("loop0", None),
+            ("loop-1", None),
+            ("loop70000", None), // More than a u16
+            ("loopx", None),
+            ("rotate45", None),
+            ("", None),
+            ("flip ", None),
+        ];
+
+        for (s, expected) in cases {
+            assert_eq!(Modifier::parse(s), expected, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_list() {
+        let cases: Vec<(&str, Vec<Modifier>)> = vec![
+            ("", vec![]),
+            (":2x:flip", vec![ Modifier::Scale(2, 1), Modifier::FlipHorizontal ]),
+            (":0x:flip::nope:", vec![ Modifier::FlipHorizontal ]), // Unknown and malformed ones are ignored
+            (":flip:flip", vec![ Modifier::FlipHorizontal, Modifier::FlipHorizontal ]),
+        ];
+
+        for (s, expected) in cases {
+            assert_eq!(parse(s), expected, "{:?}", s);
+        }
+        assert_eq!(key(&parse(":half:2x:fast:loop2")), "half:2/1x:speed2/1:loop2");
+    }
+
+    #[test]
+    fn retime_frames() {
+        fn frames(delays: &[u32]) -> Vec<Frame> {
+            delays
+                .iter()
+                .map(|delay| Frame::from_parts(RgbaImage::new(1, 1), 0, 0, Delay::from_numer_denom_ms(*delay, 1)))
+                .collect()
+        }
+        fn delays(frames: &[Frame]) -> Vec<u32> {
+            frames
+                .iter()
+                .map(|frame| {
+                    let (numerator, denominator) = frame.delay().numer_denom_ms();
+                    numerator / denominator
+                })
+                .collect()
+        }
+
+        // Frame delays, speed numerator and denominator, expected delays
+        let cases: Vec<(Vec<u32>, u32, u32, Vec<u32>)> = vec![
+            (vec![ 100, 100 ], 2, 1, vec![ 50, 50 ]),
+            (vec![ 100, 100 ], 1, 2, vec![ 200, 200 ]),
+            (vec![ 30, 30, 30, 30 ], 2, 1, vec![ 30, 30 ]), // Frames under the minimum delay are merged
+            (vec![ 30, 30, 30 ], 2, 1, vec![ 30, 20 ]), // The last frame is always kept
+            (vec![ 10 ], 4, 1, vec![ 20 ]),
+            (vec![], 2, 1, vec![]),
+        ];
+
+        for (input, numerator, denominator, expected) in cases {
+            let retimed = retime(frames(&input), numerator, denominator);
+            assert_eq!(delays(&retimed), expected, "delays: {:?}, speed: {}/{}", input, numerator, denominator);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    sync::{ Arc, RwLock },
+    time::{ Duration, Instant },
+};
+
+use super::EmoteSource;
+use crate::{
+    media,
+    www::library,
+    emote_manager::Emote,
+    Error, ErrorKind, Result,
+};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+struct IndexEntry {
+    pub name: String,
+    pub url: String,
+}
+
+/// Emotes listed in a JSON document served over HTTP, as an array of `{ "name": ..., "url": ... }` objects.
+pub struct JsonIndexSource {
+    url: String,
+    refresh_interval: Duration,
+    index: RwLock<Option<(Instant, Arc<Vec<IndexEntry>>)>>,
+}
+
+impl JsonIndexSource {
+    pub fn new(url: &str, refresh_interval: u64) -> Self {
+        Self {
+            url: url.to_owned(),
+            refresh_interval: Duration::from_secs(refresh_interval),
+            index: RwLock::new(None),
+        }
+    }
+
+    /// Returns the index, downloading it again if it is older than the refresh interval.
+    /// The previous index is kept if the download fails.
+    fn index(&self) -> Result<Arc<Vec<IndexEntry>>> {
+        let previous = self.index.read().map_err(|_err| Error::new(ErrorKind::ManagerRead))?.clone();
+        if let Some((fetched_at, index)) = &previous {
+            if fetched_at.elapsed() < self.refresh_interval {
+                return Ok(index.clone());
+            }
+        }
+
+        let index = match self.download_index() {
+            Ok(index) => Arc::new(index),
+            Err(err) => match previous {
+                Some((_fetched_at, index)) => {
+                    log::warn!("Could not refresh emote index {}: {}", self.url, err);
+                    return Ok(index);
+                },
+                None => return Err(err),
+            },
+        };
+        *self.index.write().map_err(|_err| Error::new(ErrorKind::ManagerWrite))? = Some((Instant::now(), index.clone()));
+        Ok(index)
+    }
+
+    fn download_index(&self) -> Result<Vec<IndexEntry>> {
+        let res = reqwest::blocking::get(&self.url)?.error_for_status()?;
+        let mut index: Vec<IndexEntry> = res.json()?;
+        for entry in index.iter_mut() {
+            entry.name = entry.name.to_lowercase();
+        }
+        Ok(index)
+    }
+}
+
+impl EmoteSource for JsonIndexSource {
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<library::Emote>> {
+        let query = query.to_lowercase();
+
+        Ok(self.index()?
+                .iter()
+                .filter(|entry| entry.name.contains(&query))
+                .take(limit)
+                .map(|entry| library::Emote {
+                    name: entry.name.clone(),
+                    url: entry.url.clone(),
+                    ..Default::default()
+                })
+                .collect())
+    }
+
+    fn fetch(&self, name: &str) -> Result<Option<Arc<Emote>>> {
+        let name = name.to_lowercase();
+        let index = self.index()?;
+        let entry = match index.iter().find(|entry| entry.name == name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let mut res = reqwest::blocking::get(&entry.url)?.error_for_status()?;
+        let content_type = res.headers()
+                                .get(reqwest::header::CONTENT_TYPE)
+                                .and_then(|val| val.to_str().ok())
+                                .map(String::from);
+        let mut bytes: Vec<u8> = Vec::new();
+        res.copy_to(&mut bytes)?;
+        let (bytes, format) = media::normalize(bytes, content_type.as_deref())?;
+        Ok(Some(Arc::new(Emote::from_bytes(name.clone(), format!("{}.{}", name, format.extension()), bytes))))
+    }
+
+    fn suggest(&self, name: &str, limit: usize) -> Result<Vec<String>> {
+        Ok(self.search(name, limit)?.into_iter().map(|emote| emote.name).collect())
+    }
+}
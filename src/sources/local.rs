@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use super::EmoteSource;
+use crate::{
+    www::library,
+    emote_manager::{ Emote, EmoteManager },
+    Result,
+};
+
+/// The local assets directory is served by the `EmoteManager` itself.
+impl EmoteSource for EmoteManager {
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<library::Emote>> {
+        Ok(self.search_emotes(query, limit)?
+                .iter()
+                .filter_map(|(emote, _score)| {
+                    let path = emote.path.strip_prefix(self.assets_directory()).ok()?;
+                    Some(library::Emote::new(emote, path))
+                })
+                .collect())
+    }
+
+    fn fetch(&self, name: &str) -> Result<Option<Arc<Emote>>> {
+        self.find_emote_by_name(name)
+    }
+
+    fn autocorrect(&self, name: &str) -> Result<Option<Arc<Emote>>> {
+        self.autocorrect_emote(name)
+    }
+
+    fn suggest(&self, name: &str, limit: usize) -> Result<Vec<String>> {
+        Ok(self.search_emotes(name, limit)?
+                .into_iter()
+                .map(|(emote, _score)| emote.qualified_name())
+                .collect())
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    sync::Arc,
+    collections::HashMap,
+};
+
+use crate::{
+    www::library,
+    twitch_cache::TwitchCache,
+    emote_manager::Emote,
+    config::{ Config, SourceConfig },
+    Result,
+};
+
+pub mod local;
+
+pub mod twitch;
+pub use twitch::TwitchManagerSource;
+
+pub mod json_index;
+pub use json_index::JsonIndexSource;
+
+/// Name of the source backed by the local assets directory, which is always available.
+pub const LOCAL_SOURCE: &str = "local";
+/// Name of the source created from the `www` Twitch emotes manager settings when it is not defined in `sources`.
+pub const TWITCH_SOURCE: &str = "twitch";
+
+pub trait EmoteSource: Send + Sync {
+    /// Lists the emotes whose name contains `query`, in the format served by the web library.
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<library::Emote>>;
+
+    /// Returns the emote named exactly `name`, if this source has one.
+    fn fetch(&self, name: &str) -> Result<Option<Arc<Emote>>>;
+
+    /// Returns the emote closest to `name` when there is a clear candidate.
+    fn autocorrect(&self, _name: &str) -> Result<Option<Arc<Emote>>> {
+        Ok(None)
+    }
+
+    /// Returns the names of the emotes closest to `name`, best match first.
+    fn suggest(&self, _name: &str, _limit: usize) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Writes to disk what the source only keeps in memory, called on shutdown.
+    fn save(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates the remote sources declared in the configuration, the local one is the `EmoteManager` itself.
+pub fn build(config: &Config) -> Result<HashMap<String, Box<dyn EmoteSource>>> {
+    let cache = Arc::new(TwitchCache::new(&config.twitch_cache)?);
+    let mut sources: HashMap<String, Box<dyn EmoteSource>> = HashMap::new();
+
+    for (name, source_config) in config.sources.iter() {
+        let source: Box<dyn EmoteSource> = match source_config {
+            SourceConfig::TwitchManager { host, port } => Box::new(TwitchManagerSource::new(name, host, *port, cache.clone())),
+            SourceConfig::JsonIndex { url, refresh_interval } => Box::new(JsonIndexSource::new(url, *refresh_interval)),
+        };
+        sources.insert(name.clone(), source);
+    }
+
+    if !sources.contains_key(TWITCH_SOURCE) {
+        sources.insert(TWITCH_SOURCE.to_owned(), Box::new(TwitchManagerSource::new(
+            TWITCH_SOURCE,
+            &config.www.twitch_emotes_manager_host,
+            config.www.twitch_emotes_manager_port,
+            cache,
+        )));
+    }
+
+    Ok(sources)
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use super::EmoteSource;
+use crate::{
+    media,
+    www::library,
+    twitch_cache::TwitchCache,
+    emote_manager::Emote,
+    Result,
+};
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct ManagerSearchResult {
+    pub id: u64,
+    pub name: String,
+    pub url: String,
+}
+
+/// Emotes served by a Twitch emotes manager instance.
+pub struct TwitchManagerSource {
+    name: String,
+    host: String,
+    port: u16,
+    cache: Arc<TwitchCache>,
+}
+
+impl TwitchManagerSource {
+    pub fn new(name: &str, host: &str, port: u16, cache: Arc<TwitchCache>) -> Self {
+        Self {
+            name: name.to_owned(),
+            host: host.to_owned(),
+            port,
+            cache,
+        }
+    }
+
+    fn search_manager(&self, query: &str, limit: usize, exact_match: bool) -> Result<Vec<ManagerSearchResult>> {
+        let query = query.to_lowercase();
+        let limit = limit.min(50);
+
+        let url = format!(
+            "http://{}:{}/emotes/search?q={}&maxresults={}&exactmatch={}",
+            self.host, self.port,
+            query, limit, if exact_match { 1 } else { 0 },
+        );
+        let res = reqwest::blocking::get(&url)?;
+        Ok(res.json()?)
+    }
+
+    fn emote_url(result: &ManagerSearchResult) -> String {
+        result.url.replace("2.0", "3.0").replace("1.0", "3.0")
+    }
+
+    /// Downloads an emote, returning its id and bytes converted to a format Discord can display.
+    fn download(&self, name: &str) -> Result<Option<(u64, Vec<u8>)>> {
+        let res = self.search_manager(name, 1, true)?;
+        Ok(match res.first() {
+            Some(emote) => {
+                let mut res = reqwest::blocking::get(&Self::emote_url(emote))?;
+                let content_type = res.headers()
+                                        .get(reqwest::header::CONTENT_TYPE)
+                                        .and_then(|val| val.to_str().ok())
+                                        .map(String::from);
+                let mut bytes: Vec<u8> = Vec::new();
+                res.copy_to(&mut bytes)?;
+                let (bytes, _format) = media::normalize(bytes, content_type.as_deref())?;
+                Some((emote.id, bytes))
+            },
+            None => None,
+        })
+    }
+}
+
+impl EmoteSource for TwitchManagerSource {
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<library::Emote>> {
+        let res = self.search_manager(query, limit, false)?;
+
+        Ok(res.iter().map(|e| library::Emote {
+            name: e.name.clone(),
+            url: Self::emote_url(e),
+            ..Default::default()
+        }).collect())
+    }
+
+    /// Looks up the emote in the disk cache first, and only asks the manager when it is missing or stale.
+    /// A stale cached emote is still returned if the manager cannot be reached.
+    fn fetch(&self, name: &str) -> Result<Option<Arc<Emote>>> {
+        let name = name.to_lowercase();
+        let key = format!("{}/{}", self.name, name);
+        let make_emote = |bytes: Vec<u8>| Arc::new(Emote::from_bytes(name.clone(), media::file_name(&name, &bytes), bytes));
+
+        let cached = match self.cache.get(&key) {
+            Ok(Some(cached)) if cached.fresh => return Ok(Some(make_emote(cached.bytes))),
+            Ok(cached) => cached,
+            Err(err) => {
+                log::warn!("Could not read Twitch emote \"{}\" from the cache: {}", key, err);
+                None
+            },
+        };
+
+        match self.download(&name) {
+            Ok(Some((id, bytes))) => {
+                if let Err(err) = self.cache.put(&key, id, &bytes) {
+                    log::warn!("Could not write Twitch emote \"{}\" to the cache: {}", key, err);
+                }
+                Ok(Some(make_emote(bytes)))
+            },
+            Ok(None) => Ok(None),
+            Err(err) => match cached {
+                Some(cached) => {
+                    log::warn!("Using stale cached Twitch emote \"{}\": {}", key, err);
+                    Ok(Some(make_emote(cached.bytes)))
+                },
+                None => Err(err),
+            },
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        self.cache.save()
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    fs,
+    sync::{ Arc, Mutex, MutexGuard },
+    time::{ Duration, Instant },
+    collections::{ BTreeMap, HashMap },
+};
+
+use crate::{
+    config::StatsConfig,
+    Error, ErrorKind, Result,
+};
+
+use typemap::Key;
+use serde::Serialize;
+
+/// Local emotes are counted under their qualified name, emotes of other sources as `source:name`
+/// and text emotes under this source, e.g. `text:shrug`.
+pub const TEXT_SOURCE: &str = "text";
+
+/// Uses of each emote, by day (`YYYY-MM-DD`) then by user.
+type Counts = BTreeMap<String, BTreeMap<u64, BTreeMap<String, u64>>>;
+
+struct State {
+    counts: Counts,
+    last_save: Instant,
+    dirty: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EmoteCount {
+    pub name: String,
+    pub count: u64,
+}
+
+/// Counts how many times each emote is used, saved to a JSON file every `flush_interval` seconds.
+pub struct UsageStats {
+    config: StatsConfig,
+    state: Mutex<State>,
+}
+
+impl UsageStats {
+    pub fn new(config: &StatsConfig) -> Result<Self> {
+        let mut counts = Counts::new();
+        if config.enabled && config.file.is_file() {
+            match serde_json::from_slice(&fs::read(&config.file)?) {
+                Ok(val) => counts = val,
+                Err(err) => log::warn!("Discarding unreadable emote usage statistics: {}", err),
+            };
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            state: Mutex::new(State {
+                counts,
+                last_save: Instant::now(),
+                dirty: false,
+            }),
+        })
+    }
+
+    fn lock_state(&self) -> Result<MutexGuard<State>> {
+        self.state.lock().map_err(|_err| Error::new(ErrorKind::Stats))
+    }
+
+    fn today() -> String {
+        chrono::Local::today().format("%Y-%m-%d").to_string()
+    }
+
+    /// Name under which an emote that is not in the local library is counted.
+    pub fn usage_name(source: &str, name: &str) -> String {
+        format!("{}:{}", source, name)
+    }
+
+    pub fn record(&self, user: u64, emote: &str) -> Result<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        let mut state = self.lock_state()?;
+        *state.counts
+                .entry(Self::today())
+                .or_default()
+                .entry(user)
+                .or_default()
+                .entry(emote.to_owned())
+                .or_default() += 1;
+        state.dirty = true;
+
+        if state.last_save.elapsed() >= Duration::from_secs(self.config.flush_interval) {
+            self.save_state(&mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the statistics to disk if they changed since the last save.
+    pub fn save(&self) -> Result<()> {
+        let mut state = self.lock_state()?;
+        self.save_state(&mut state)
+    }
+
+    fn save_state(&self, state: &mut State) -> Result<()> {
+        state.last_save = Instant::now();
+        if !state.dirty {
+            return Ok(());
+        }
+
+        let tmp_path = self.config.file.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&state.counts)?).map_err(|err| Error::from(ErrorKind::Stats, err))?;
+        fs::rename(&tmp_path, &self.config.file).map_err(|err| Error::from(ErrorKind::Stats, err))?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    /// Adds up the uses of each emote over the last `days` days (all of them if `None`), optionally for a single user.
+    /// The most used emotes come first.
+    pub fn top(&self, days: Option<u32>, user: Option<u64>) -> Result<Vec<EmoteCount>> {
+        let since = days.map(|days| {
+            let first_day = chrono::Local::today() - chrono::Duration::days(i64::from(days.max(1)) - 1);
+            first_day.format("%Y-%m-%d").to_string()
+        });
+
+        let state = self.lock_state()?;
+        let mut totals: HashMap<&str, u64> = HashMap::new();
+        let recent_days = state.counts
+                                .iter()
+                                .filter(|(day, _users)| since.as_ref().map_or(true, |since| day.as_str() >= since.as_str()));
+        for (_day, users) in recent_days {
+            let counts = users
+                            .iter()
+                            .filter(|(user_id, _counts)| user.map_or(true, |user| **user_id == user))
+                            .flat_map(|(_user_id, counts)| counts.iter());
+            for (name, count) in counts {
+                *totals.entry(name.as_str()).or_default() += count;
+            }
+        }
+
+        let mut top = totals
+                        .into_iter()
+                        .map(|(name, count)| EmoteCount {
+                            name: name.to_owned(),
+                            count,
+                        })
+                        .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        Ok(top)
+    }
+}
+
+impl Key for UsageStats {
+    type Value = Arc<UsageStats>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    // Days, user, expected names and counts
+    type TopCase = (Option<u32>, Option<u64>, Vec<(&'static str, u64)>);
+
+    fn stats(directory: &TempDir) -> UsageStats {
+        UsageStats::new(&StatsConfig {
+            enabled: true,
+            file: directory.path().join("stats.json"),
+            flush_interval: 3600,
+        }).unwrap()
+    }
+
+    fn days_ago(days: i64) -> String {
+        (chrono::Local::today() - chrono::Duration::days(days)).format("%Y-%m-%d").to_string()
+    }
+
+    fn counts(top: Vec<EmoteCount>) -> Vec<(String, u64)> {
+        top.into_iter().map(|count| (count.name, count.count)).collect()
+    }
+
+    #[test]
+    fn top() {
+        let directory = TempDir::new("stats");
+        let stats = stats(&directory);
+        for (user, emote) in [ (1, "kappa"), (1, "kappa"), (1, "pog"), (2, "kappa") ].iter() {
+            stats.record(*user, emote).unwrap();
+        }
+        {
+            let mut state = stats.lock_state().unwrap();
+            state.counts.entry(days_ago(3)).or_default().entry(1).or_default().insert("pog".to_owned(), 5);
+            state.counts.entry(days_ago(10)).or_default().entry(2).or_default().insert("text:shrug".to_owned(), 7);
+        }
+
+        let cases: Vec<TopCase> = vec![
+            (None, None, vec![ ("text:shrug", 7), ("pog", 6), ("kappa", 3) ]),
+            (Some(1), None, vec![ ("kappa", 3), ("pog", 1) ]), // Today only
+            (Some(0), None, vec![ ("kappa", 3), ("pog", 1) ]),
+            (Some(4), None, vec![ ("pog", 6), ("kappa", 3) ]),
+            (Some(4), Some(1), vec![ ("pog", 6), ("kappa", 2) ]),
+            (None, Some(2), vec![ ("text:shrug", 7), ("kappa", 1) ]),
+            (None, Some(3), vec![]),
+        ];
+
+        for (days, user, expected) in cases {
+            let expected = expected.into_iter().map(|(name, count)| (name.to_owned(), count)).collect::<Vec<_>>();
+            assert_eq!(counts(stats.top(days, user).unwrap()), expected, "days: {:?}, user: {:?}", days, user);
+        }
+        // Same count, sorted by name
+        stats.record(2, "pog").unwrap();
+        stats.record(2, "pog").unwrap();
+        assert_eq!(counts(stats.top(Some(1), None).unwrap()), vec![ ("kappa".to_owned(), 3), ("pog".to_owned(), 3) ]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let directory = TempDir::new("stats");
+        let stats = stats(&directory);
+        stats.record(1, "kappa").unwrap();
+        stats.record(2, "kappa").unwrap();
+        stats.record(2, "twitch:pogchamp").unwrap();
+        stats.save().unwrap();
+
+        let loaded = self::stats(&directory);
+        assert_eq!(loaded.top(None, None).unwrap(), stats.top(None, None).unwrap());
+        assert_eq!(counts(loaded.top(None, Some(2)).unwrap()), vec![ ("kappa".to_owned(), 1), ("twitch:pogchamp".to_owned(), 1) ]);
+    }
+}
@@ -0,0 +1,50 @@
+//! Helpers shared by the tests.
+
+use std::{
+    path::{ Path, PathBuf },
+    sync::atomic::{ AtomicUsize, Ordering },
+};
+
+use image::{ DynamicImage, ImageOutputFormat, RgbaImage };
+
+/// Makes the directories of the tests running in parallel unique.
+static DIRECTORY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory, removed with its content at the end of the test.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("selfportrait-{}-{}-{}", name, std::process::id(), DIRECTORY_COUNT.fetch_add(1, Ordering::SeqCst)));
+        std::fs::create_dir_all(&path).unwrap();
+        Self {
+            path,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A transparent PNG image.
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut png = Vec::new();
+    DynamicImage::ImageRgba8(RgbaImage::new(width, height)).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+    png
+}
+
+/// Writes a file next to its destination then moves it into place, like the bot does.
+pub fn replace_file(path: &Path, bytes: &[u8]) {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, bytes).unwrap();
+    std::fs::rename(&tmp_path, path).unwrap();
+}
@@ -0,0 +1,17 @@
+use crate::{
+    Result,
+    config::Config,
+    EmoteManager,
+};
+
+/// Prints the emotes that use the same name, and how the configured policy resolves them.
+pub fn run(config: &Config) -> Result<()> {
+    let manifest = EmoteManager::load_manifest(&config.emotes.directory)?;
+    let conflicts = EmoteManager::scan(&config.emotes, &manifest)?.conflicts;
+
+    for conflict in conflicts.iter() {
+        println!("{}", conflict);
+    }
+    println!("{} conflict{} found.", conflicts.len(), if conflicts.len() == 1 { "" } else { "s" });
+    Ok(())
+}
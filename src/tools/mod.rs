@@ -1 +1,2 @@
 pub mod print_config;
+pub mod conflicts;
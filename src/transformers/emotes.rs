@@ -0,0 +1,205 @@
+use super::*;
+
+use crate::{
+    modifiers,
+    bot::planner,
+    config::EmoteCorrection,
+    stats::UsageStats,
+    sources::{ EmoteSource, LOCAL_SOURCE },
+    emote_manager::EmoteManager,
+    error::{ Error, ErrorKind },
+};
+
+use regex::Regex;
+
+/// Replaces the emotes typed with one of the user's emote prefixes, such as `>kappa`, with their file.
+#[derive(Default)]
+pub struct Emotes;
+
+impl Emotes {
+    pub fn boxed() -> Box<Self> {
+        Box::new(Self::default())
+    }
+
+    /// Splits a text at the emotes that can be found, the others are left in the text.
+    /// Returns the names of the emotes that were not found, with their prefix.
+    fn split_text(bot: &Bot, mngr: &EmoteManager, re: &Regex, text: &str, segments: &mut Vec<Segment<QueuedEmote>>) -> Vec<(String, String)> {
+        let mut unknown = Vec::new();
+        let split = planner::split(text, re, |prefix, names, modifiers| {
+            match Self::find_combined_emote(bot, mngr, prefix, names) {
+                Ok((usage, emote)) => Some(QueuedEmote {
+                    emote: Self::apply_modifiers(bot.emote_owner(mngr, &emote), emote, modifiers),
+                    usage,
+                }),
+                Err(missing) => {
+                    unknown.extend(missing.into_iter().map(|name| (prefix.to_owned(), name.to_owned())));
+                    None
+                },
+            }
+        });
+
+        segments.extend(split);
+        unknown
+    }
+
+    /// Returns the sources configured for an emote prefix, in the order they should be searched.
+    /// The private emotes of the user come before the shared library.
+    fn emote_sources<'a>(bot: &'a Bot, mngr: &'a EmoteManager, prefix: &str) -> Vec<(&'a str, &'a dyn EmoteSource)> {
+        let binding = match bot.user.emote_sources.iter().find(|binding| binding.prefix == prefix) {
+            Some(binding) => binding,
+            None => return Vec::new(),
+        };
+
+        let mut sources = Vec::new();
+        for source_name in binding.sources.iter() {
+            if let (LOCAL_SOURCE, Some(private_emotes)) = (source_name.as_str(), bot.private_emotes()) {
+                sources.push((source_name.as_str(), private_emotes.as_ref() as &dyn EmoteSource));
+            }
+            match mngr.source(source_name) {
+                Some(source) => sources.push((source_name.as_str(), source)),
+                None => log::warn!("Unknown emote source \"{}\"", source_name),
+            };
+        }
+        sources
+    }
+
+    /// Looks for an emote in every source bound to `prefix`, then tries to autocorrect the name if the user enabled it.
+    /// Errors from a source are logged so that the next sources can still be searched.
+    /// Also returns the name under which the emote is counted in the usage statistics.
+    fn find_emote(bot: &Bot, mngr: &EmoteManager, prefix: &str, name: &str) -> Option<(String, Arc<Emote>)> {
+        if name.is_empty() {
+            return None;
+        }
+
+        let sources = Self::emote_sources(bot, mngr, prefix);
+        for (source_name, source) in sources.iter() {
+            match source.fetch(name) {
+                Ok(Some(emote)) => return Some((Self::usage_name(source_name, &emote), emote)),
+                Ok(None) => {},
+                Err(err) => log::warn!("Could not fetch emote \"{}\" from source \"{}\": {}", name, source_name, err),
+            };
+        }
+
+        if bot.user.emote_correction == EmoteCorrection::AutoCorrect {
+            for (source_name, source) in sources.iter() {
+                match source.autocorrect(name) {
+                    Ok(Some(emote)) => return Some((Self::usage_name(source_name, &emote), emote)),
+                    Ok(None) => {},
+                    Err(err) => log::warn!("Could not autocorrect emote \"{}\" from source \"{}\": {}", name, source_name, err),
+                };
+            }
+        }
+        None
+    }
+
+    /// Looks for every emote of a combination such as `pepe+kappa` and composes them into one image.
+    /// Returns the names of the missing emotes if any of them is missing, none if they could not be combined.
+    fn find_combined_emote<'a>(bot: &Bot, mngr: &EmoteManager, prefix: &str, names: &'a str) -> std::result::Result<(Vec<String>, Arc<Emote>), Vec<&'a str>> {
+        let mut found = Vec::new();
+        let mut missing = Vec::new();
+        for name in names.split('+') {
+            match Self::find_emote(bot, mngr, prefix, name) {
+                Some(emote) => found.push(emote),
+                None if !name.is_empty() => missing.push(name),
+                None => {}, // An empty name such as in `a++b` is not worth a suggestion
+            };
+        }
+        if found.len() < names.split('+').count() {
+            return Err(missing);
+        }
+
+        let (usage, emotes): (Vec<_>, Vec<_>) = found.into_iter().unzip();
+        if let [emote] = emotes.as_slice() {
+            return Ok((usage, emote.clone()));
+        }
+
+        // The emotes may come from different libraries, each one loads its own files
+        let loaded = emotes.iter().map(|emote| bot.emote_owner(mngr, emote).loaded(emote)).collect::<Result<Vec<_>>>();
+        match loaded.and_then(|emotes| mngr.combined(&emotes)) {
+            Ok(combined) => Ok((usage, combined)),
+            Err(err) => {
+                log::warn!("Could not combine emotes \"{}\": {}", names, err);
+                Err(Vec::new())
+            },
+        }
+    }
+
+    /// Renders the emote with the modifiers typed after its name, sending it unmodified if that fails.
+    fn apply_modifiers(mngr: &EmoteManager, emote: Arc<Emote>, modifiers: &str) -> Arc<Emote> {
+        let modifiers = modifiers::parse(modifiers);
+        match mngr.modified(&emote, &modifiers) {
+            Ok(modified) => modified,
+            Err(err) => {
+                log::warn!("Could not apply modifiers to emote \"{}\": {}", emote.name, err);
+                emote
+            },
+        }
+    }
+
+    fn usage_name(source_name: &str, emote: &Emote) -> String {
+        if source_name == LOCAL_SOURCE {
+            emote.qualified_name()
+        } else {
+            UsageStats::usage_name(source_name, &emote.name)
+        }
+    }
+
+    fn suggest_emotes(bot: &Bot, ctx: &Context, mngr: &EmoteManager, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, unknown: &[(String, String)]) -> Result<()> {
+        for (prefix, name) in unknown {
+            let mut suggestions: Vec<String> = Vec::new();
+            for (_source_name, source) in Self::emote_sources(bot, mngr, prefix) {
+                for suggestion in source.suggest(name, 3).unwrap_or_default() {
+                    let suggestion = format!("`{}{}`", prefix, suggestion);
+                    if !suggestions.contains(&suggestion) {
+                        suggestions.push(suggestion);
+                    }
+                }
+            }
+            suggestions.truncate(3);
+            if suggestions.is_empty() {
+                continue;
+            }
+
+            let content = format!("Unknown emote `{}{}`, did you mean {}?", prefix, name, suggestions.join(", "));
+            bot.send_message(ctx, msg, event, &content)?;
+        }
+        Ok(())
+    }
+}
+
+impl MessageTransformer for Emotes {
+    fn name(&self) -> &'static str {
+        "emotes"
+    }
+
+    fn transform(&self, bot: &Bot, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, draft: &mut MessageDraft) -> Result<Flow> {
+        let text = draft.text();
+        if !bot.user.emote_sources.iter().any(|binding| binding.prefix.is_empty() || text.contains(&binding.prefix)) {
+            return Ok(Flow::Continue);
+        }
+
+        let prefixes = bot.user.emote_sources
+                            .iter()
+                            .map(|binding| binding.prefix.as_str())
+                            .collect::<Vec<_>>();
+        let re = planner::emote_regex(&prefixes);
+
+        let data = ctx.data.read();
+        let mngr = data.get::<EmoteManager>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+
+        let mut segments = Vec::with_capacity(draft.segments.len());
+        let mut unknown = Vec::new();
+        for segment in draft.segments.drain(..) {
+            match segment {
+                Segment::Text(text) => unknown.append(&mut Self::split_text(bot, mngr, &re, &text, &mut segments)),
+                emote => segments.push(emote),
+            };
+        }
+        draft.segments = segments;
+
+        if bot.user.emote_correction == EmoteCorrection::Suggest {
+            Self::suggest_emotes(bot, ctx, mngr, msg, event, &unknown)?;
+        }
+        Ok(Flow::Continue)
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use crate::{
+    bot::Bot,
+    error::Result,
+    emote_manager::Emote,
+};
+pub use crate::bot::planner::{ Attachment, Segment };
+
+use serenity::{
+    prelude::*,
+    model::channel::Message,
+    model::event::MessageUpdateEvent,
+};
+
+pub mod spoiler;
+pub use spoiler::Spoiler;
+
+pub mod text_emotes;
+pub use text_emotes::TextEmotes;
+
+pub mod emotes;
+pub use emotes::Emotes;
+
+/// Stages run on the messages of a user when their configuration does not list any.
+pub const DEFAULT_PIPELINE: &[&str] = &[ "spoiler", "text_emotes", "emotes" ];
+
+/// What the pipeline does after a stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Continue,
+    Stop, // Skip the next stages, the message is still updated with what the previous ones did
+}
+
+/// An emote to send with the message.
+pub struct QueuedEmote {
+    pub emote: Arc<Emote>,
+    pub usage: Vec<String>, // Names under which the emotes are counted in the statistics
+}
+
+impl Attachment for QueuedEmote {
+    fn size(&self) -> u64 {
+        self.emote.size
+    }
+}
+
+/// The message as rewritten by the stages of the pipeline so far.
+pub struct MessageDraft {
+    pub segments: Vec<Segment<QueuedEmote>>,
+    pub spoiler_mode: bool, // Whether spoiler mode is enabled in the channel of the message
+    pub usage: Vec<String>, // Text emotes to count in the statistics once the message is updated
+}
+
+impl MessageDraft {
+    pub fn new(content: String, spoiler_mode: bool) -> Self {
+        Self {
+            segments: vec![ Segment::Text(content) ],
+            spoiler_mode,
+            usage: Vec::new(),
+        }
+    }
+
+    pub fn has_emotes(&self) -> bool {
+        self.segments.iter().any(|segment| matches!(segment, Segment::Emote(_)))
+    }
+
+    /// The text of the message, without the emotes.
+    pub fn text(&self) -> String {
+        self.segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Text(text) => Some(text.as_str()),
+                Segment::Emote(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn texts_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.segments.iter_mut().filter_map(|segment| match segment {
+            Segment::Text(text) => Some(text),
+            Segment::Emote(_) => None,
+        })
+    }
+}
+
+/// A stage of the pipeline that the messages of a user go through after the commands.
+/// Stages can rewrite the text of the message, queue emotes to send with it, or stop the pipeline.
+pub trait MessageTransformer {
+    /// Name under which the stage is listed in the `transformers` of a user.
+    fn name(&self) -> &'static str;
+    fn transform(&self, bot: &Bot, ctx: &Context, msg: &Option<&mut Message>, event: &Option<&MessageUpdateEvent>, draft: &mut MessageDraft) -> Result<Flow>;
+}
+
+/// Creates the stages of a pipeline in the given order.
+pub fn build(names: &[String]) -> Vec<Box<dyn MessageTransformer + Send + Sync>> {
+    names
+        .iter()
+        .filter_map(|name| -> Option<Box<dyn MessageTransformer + Send + Sync>> {
+            match name.as_str() {
+                "spoiler" => Some(Spoiler::boxed()),
+                "text_emotes" => Some(TextEmotes::boxed()),
+                "emotes" => Some(Emotes::boxed()),
+                _ => {
+                    log::warn!("Unknown message transformer \"{}\"", name);
+                    None
+                },
+            }
+        })
+        .collect()
+}
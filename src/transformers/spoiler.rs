@@ -0,0 +1,34 @@
+use super::*;
+
+use crate::commands;
+
+/// Wraps the message in spoiler pipes in the channels where spoiler mode is enabled.
+#[derive(Default)]
+pub struct Spoiler;
+
+impl Spoiler {
+    pub fn boxed() -> Box<Self> {
+        Box::new(Self::default())
+    }
+}
+
+impl MessageTransformer for Spoiler {
+    fn name(&self) -> &'static str {
+        "spoiler"
+    }
+
+    fn transform(&self, _bot: &Bot, _ctx: &Context, _msg: &Option<&mut Message>, _event: &Option<&MessageUpdateEvent>, draft: &mut MessageDraft) -> Result<Flow> {
+        // The text around emotes is spoilered separately when the message is sent
+        if !draft.spoiler_mode || draft.has_emotes() {
+            return Ok(Flow::Continue);
+        }
+
+        let mut text = draft.text();
+        if text.trim().is_empty() || !commands::Spoiler::spoilerize(&mut text) {
+            return Ok(Flow::Continue);
+        }
+        draft.segments = vec![ Segment::Text(text) ];
+        // Editing the message triggers an update event, the next stages run on the spoilered message then
+        Ok(Flow::Stop)
+    }
+}
@@ -0,0 +1,48 @@
+use super::*;
+
+use crate::{
+    EmoteManager,
+    stats::{ self, UsageStats },
+    error::{ Error, ErrorKind },
+};
+
+/// Replaces the triggers of text emotes, such as `$shrug`, with their text.
+#[derive(Default)]
+pub struct TextEmotes;
+
+impl TextEmotes {
+    pub fn boxed() -> Box<Self> {
+        Box::new(Self::default())
+    }
+}
+
+impl MessageTransformer for TextEmotes {
+    fn name(&self) -> &'static str {
+        "text_emotes"
+    }
+
+    fn transform(&self, bot: &Bot, ctx: &Context, _msg: &Option<&mut Message>, _event: &Option<&MessageUpdateEvent>, draft: &mut MessageDraft) -> Result<Flow> {
+        let prefix = &bot.user.text_emote_prefix;
+        if prefix.is_empty() || !draft.text().contains(prefix) {
+            return Ok(Flow::Continue);
+        }
+
+        let data = ctx.data.read();
+        let mngr = data.get::<EmoteManager>().ok_or_else(|| Error::new(ErrorKind::DataGet))?;
+        let replacements = mngr.text_emotes_for(&bot.user.text_emotes);
+        let mut used = Vec::new();
+
+        for text in draft.texts_mut() {
+            for (trigger, replacement) in replacements.iter() {
+                let pattern = format!("{}{}", prefix, trigger);
+                for _ in text.matches(&pattern) {
+                    used.push(UsageStats::usage_name(stats::TEXT_SOURCE, trigger));
+                }
+                *text = text.replace(&pattern, replacement);
+            }
+        }
+        draft.usage.append(&mut used);
+
+        Ok(Flow::Continue)
+    }
+}
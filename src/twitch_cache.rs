@@ -0,0 +1,295 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{ Mutex, MutexGuard },
+    collections::{ HashMap, HashSet },
+    time::{ Duration, Instant, SystemTime, UNIX_EPOCH },
+};
+
+use crate::{
+    config::TwitchCacheConfig,
+    Error, ErrorKind, Result,
+};
+
+use sha2::{ Sha256, Digest };
+use serde::{ Serialize, Deserialize };
+
+const INDEX_FILE: &str = "index.json";
+const BLOBS_DIRECTORY: &str = "blobs";
+/// Most time between two writes of the index when only the last uses of the entries changed.
+const INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    pub id: u64,
+    pub hash: String,
+    pub size: u64,
+    pub fetched_at: u64,
+    pub last_used: u64,
+}
+
+pub struct CachedEmote {
+    pub id: u64,
+    pub bytes: Vec<u8>,
+    pub fresh: bool, // Whether the entry is younger than the configured TTL
+}
+
+struct Index {
+    entries: HashMap<String, CacheEntry>,
+    last_save: Instant,
+    dirty: bool, // Whether entries changed since the last save
+}
+
+/// Content-addressed disk cache for Twitch emotes.
+/// The index maps emote names to the hash of their image, which is stored once in the blobs directory.
+pub struct TwitchCache {
+    config: TwitchCacheConfig,
+    index: Mutex<Index>,
+}
+
+impl TwitchCache {
+    pub fn new(config: &TwitchCacheConfig) -> Result<Self> {
+        let mut entries = HashMap::new();
+        if config.enabled {
+            fs::create_dir_all(config.directory.join(BLOBS_DIRECTORY))?;
+
+            let index_path = config.directory.join(INDEX_FILE);
+            if index_path.is_file() {
+                match serde_json::from_slice(&fs::read(&index_path)?) {
+                    Ok(val) => entries = val,
+                    Err(err) => log::warn!("Discarding unreadable Twitch emote cache index: {}", err),
+                };
+            }
+        }
+
+        Ok(Self {
+            config: config.clone(),
+            index: Mutex::new(Index {
+                entries,
+                last_save: Instant::now(),
+                dirty: false,
+            }),
+        })
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.config.directory.join(BLOBS_DIRECTORY).join(hash)
+    }
+
+    fn lock_index(&self) -> Result<MutexGuard<Index>> {
+        self.index.lock().map_err(|_err| Error::new(ErrorKind::TwitchCache))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<CachedEmote>> {
+        if !self.config.enabled {
+            return Ok(None);
+        }
+
+        let entry = match self.lock_index()?.entries.get(name) {
+            Some(entry) => entry.clone(),
+            None => return Ok(None),
+        };
+        // Read without holding the index, blobs are moved into place once complete and never rewritten
+        let bytes = fs::read(self.blob_path(&entry.hash));
+
+        let mut index = self.lock_index()?;
+        let index = &mut *index;
+        // The entry may have been replaced or evicted while the blob was read
+        let current = index.entries.get_mut(name).filter(|current| current.hash == entry.hash);
+        let now = Self::now();
+        match bytes {
+            Ok(bytes) => {
+                if let Some(current) = current {
+                    if current.last_used != now {
+                        current.last_used = now;
+                        index.dirty = true;
+                    }
+                }
+
+                // The order of the last uses only decides what is evicted first, it is saved with the next change or after a while
+                if index.dirty && index.last_save.elapsed() >= INDEX_SAVE_INTERVAL {
+                    self.save_index(index)?;
+                }
+                Ok(Some(CachedEmote {
+                    id: entry.id,
+                    bytes,
+                    fresh: now.saturating_sub(entry.fetched_at) < self.config.ttl,
+                }))
+            },
+            Err(err) => {
+                log::warn!("Dropping Twitch emote \"{}\" from the cache: {}", name, err);
+                if current.is_some() {
+                    index.entries.remove(name);
+                    self.save_index(index)?;
+                }
+                Ok(None)
+            },
+        }
+    }
+
+    pub fn put(&self, name: &str, id: u64, bytes: &[u8]) -> Result<()> {
+        let size = bytes.len() as u64;
+        if !self.config.enabled || size > self.config.max_size {
+            return Ok(());
+        }
+
+        let hash = format!("{:x}", Sha256::digest(bytes));
+        let path = self.blob_path(&hash);
+        let mut index = self.lock_index()?;
+        if !path.is_file() {
+            // Written next to the blobs first, so that a blob is never read while incomplete
+            let tmp_path = path.with_extension("tmp");
+            fs::write(&tmp_path, bytes).map_err(|err| Error::from(ErrorKind::TwitchCache, err))?;
+            fs::rename(&tmp_path, &path).map_err(|err| Error::from(ErrorKind::TwitchCache, err))?;
+        }
+
+        let now = Self::now();
+        index.entries.insert(name.to_owned(), CacheEntry {
+            id,
+            hash,
+            size,
+            fetched_at: now,
+            last_used: now,
+        });
+        self.evict(&mut index.entries)?;
+        self.save_index(&mut index)
+    }
+
+    /// Removes the least recently used entries until the blobs fit in the configured size.
+    fn evict(&self, index: &mut HashMap<String, CacheEntry>) -> Result<()> {
+        loop {
+            // Several names can point to the same blob, count each one once
+            let total: u64 = {
+                let mut hashes = HashSet::new();
+                index
+                    .values()
+                    .filter(|entry| hashes.insert(entry.hash.as_str()))
+                    .map(|entry| entry.size)
+                    .sum()
+            };
+            if total <= self.config.max_size {
+                return Ok(());
+            }
+
+            let oldest = index
+                            .iter()
+                            .min_by_key(|(_name, entry)| entry.last_used)
+                            .map(|(name, _entry)| name.clone());
+            let entry = match oldest.and_then(|name| index.remove(&name)) {
+                Some(entry) => entry,
+                None => return Ok(()),
+            };
+            if index.values().all(|other| other.hash != entry.hash) {
+                fs::remove_file(self.blob_path(&entry.hash)).map_err(|err| Error::from(ErrorKind::TwitchCache, err))?;
+            }
+        }
+    }
+
+    fn save_index(&self, index: &mut Index) -> Result<()> {
+        index.last_save = Instant::now();
+        let path = self.config.directory.join(INDEX_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&index.entries)?).map_err(|err| Error::from(ErrorKind::TwitchCache, err))?;
+        fs::rename(&tmp_path, &path).map_err(|err| Error::from(ErrorKind::TwitchCache, err))?;
+        index.dirty = false;
+        Ok(())
+    }
+
+    /// Writes the index to disk if the entries changed since the last save.
+    pub fn save(&self) -> Result<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        let mut index = self.lock_index()?;
+        if !index.dirty {
+            return Ok(());
+        }
+        self.save_index(&mut index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn cache(directory: &TempDir, max_size: u64) -> TwitchCache {
+        TwitchCache::new(&TwitchCacheConfig {
+            enabled: true,
+            directory: directory.path().to_path_buf(),
+            ttl: 60,
+            max_size,
+        }).unwrap()
+    }
+
+    fn names(cache: &TwitchCache) -> Vec<String> {
+        let mut names = cache.lock_index().unwrap().entries.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn freshness() {
+        let directory = TempDir::new("twitch-cache");
+        let cache = cache(&directory, 1024);
+        cache.put("kappa", 25, b"kappa").unwrap();
+
+        let cached = cache.get("kappa").unwrap().unwrap();
+        assert_eq!((cached.id, cached.bytes.as_slice(), cached.fresh), (25, &b"kappa"[..], true));
+
+        // Entries older than the TTL are still returned
+        cache.lock_index().unwrap().entries.get_mut("kappa").unwrap().fetched_at -= 61;
+        let cached = cache.get("kappa").unwrap().unwrap();
+        assert_eq!((cached.bytes.as_slice(), cached.fresh), (&b"kappa"[..], false));
+        assert!(cache.get("pogchamp").unwrap().is_none());
+    }
+
+    #[test]
+    fn eviction() {
+        let directory = TempDir::new("twitch-cache");
+        let cache = cache(&directory, 12);
+        cache.put("a", 1, b"aaaa").unwrap();
+        cache.put("b", 2, b"bbbb").unwrap();
+        cache.put("c", 3, b"cccc").unwrap();
+        for (name, last_used) in [ ("a", 1), ("b", 2), ("c", 3) ].iter() {
+            cache.lock_index().unwrap().entries.get_mut(*name).unwrap().last_used = *last_used;
+        }
+
+        // The blob of `same` is already stored, nothing is evicted
+        cache.put("same", 4, b"cccc").unwrap();
+        assert_eq!(names(&cache), vec![ "a", "b", "c", "same" ]);
+
+        // Using `a` makes `b` the least recently used entry
+        cache.get("a").unwrap().unwrap();
+        cache.put("d", 5, b"dddd").unwrap();
+        assert_eq!(names(&cache), vec![ "a", "c", "d", "same" ]);
+        assert!(cache.get("b").unwrap().is_none());
+        assert!(!cache.blob_path(&format!("{:x}", Sha256::digest(b"bbbb"))).exists());
+
+        // Entries larger than the cache are not stored
+        cache.put("large", 6, &[0; 13]).unwrap();
+        assert_eq!(names(&cache), vec![ "a", "c", "d", "same" ]);
+    }
+
+    #[test]
+    fn missing_blob() {
+        let directory = TempDir::new("twitch-cache");
+        let cache = cache(&directory, 1024);
+        cache.put("kappa", 25, b"kappa").unwrap();
+        cache.put("pog", 26, b"pog").unwrap();
+        fs::remove_file(cache.blob_path(&format!("{:x}", Sha256::digest(b"kappa")))).unwrap();
+
+        assert!(cache.get("kappa").unwrap().is_none());
+        assert_eq!(names(&cache), vec![ "pog" ]);
+        // The dropped entry is saved right away
+        assert_eq!(names(&self::cache(&directory, 1024)), vec![ "pog" ]);
+    }
+}
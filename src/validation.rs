@@ -0,0 +1,135 @@
+use std::{
+    fmt,
+    fs::{ self, File },
+    io::Read,
+    path::PathBuf,
+};
+
+use crate::{
+    media::{ self, MediaFormat },
+    emote_manager::Emote,
+};
+
+/// Number of bytes read to detect the format of an asset.
+const HEADER_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    Empty,
+    UnknownFormat,
+    TooLarge { size: u64, limit: u64 },
+    Unreadable(String),
+}
+
+impl Problem {
+    fn kind(&self) -> &'static str {
+        match self {
+            Problem::Empty => "empty",
+            Problem::UnknownFormat => "unknown format",
+            Problem::TooLarge { .. } => "too large",
+            Problem::Unreadable(_) => "unreadable",
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Empty => write!(f, "the file is empty"),
+            Problem::UnknownFormat => write!(f, "the file is not a supported image or sound"),
+            Problem::TooLarge { size, limit } => write!(f, "the file is {} bytes, more than the upload limit of {} bytes", size, limit),
+            Problem::Unreadable(err) => write!(f, "the file could not be read: {}", err),
+        }
+    }
+}
+
+/// An asset that was skipped when loading the emotes.
+#[derive(Debug, Clone)]
+pub struct InvalidAsset {
+    pub path: PathBuf,
+    pub format: MediaFormat,
+    pub problem: Problem,
+}
+
+impl fmt::Display for InvalidAsset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?}): {}", self.path.display(), self.format, self.problem)
+    }
+}
+
+/// The assets skipped when loading the emotes, reported along with the number of loaded emotes.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationSummary {
+    pub invalid: Vec<InvalidAsset>,
+}
+
+impl fmt::Display for ValidationSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.invalid.is_empty() {
+            return write!(f, "no invalid assets");
+        }
+        write!(f, "{} invalid asset{} skipped", self.invalid.len(), if self.invalid.len() == 1 { "" } else { "s" })?;
+
+        let mut kinds: Vec<(&str, usize)> = Vec::new();
+        for asset in self.invalid.iter() {
+            match kinds.iter_mut().find(|(kind, _count)| *kind == asset.problem.kind()) {
+                Some((_kind, count)) => *count += 1,
+                None => kinds.push((asset.problem.kind(), 1)),
+            };
+        }
+        let kinds = kinds.iter().map(|(kind, count)| format!("{} {}", count, kind)).collect::<Vec<_>>();
+        write!(f, " ({})", kinds.join(", "))
+    }
+}
+
+/// Checks that an emote file can be sent to Discord: a known format, within the upload limit,
+/// for images a header the image decoder can read, and for sounds headers giving a non-zero duration.
+pub fn validate(emote: &Emote, upload_limit: u64) -> Result<(), InvalidAsset> {
+    let invalid = |format: MediaFormat, problem: Problem| InvalidAsset {
+        path: emote.path.clone(),
+        format,
+        problem,
+    };
+
+    if emote.size == 0 {
+        return Err(invalid(MediaFormat::Unknown, Problem::Empty));
+    }
+
+    let mut header = Vec::new();
+    File::open(&emote.path)
+        .and_then(|file| file.take(HEADER_SIZE).read_to_end(&mut header))
+        .map_err(|err| invalid(MediaFormat::Unknown, Problem::Unreadable(err.to_string())))?;
+    let format = MediaFormat::sniff(&header);
+
+    if format == MediaFormat::Unknown {
+        return Err(invalid(format, Problem::UnknownFormat));
+    }
+    if emote.size > upload_limit {
+        return Err(invalid(format, Problem::TooLarge { size: emote.size, limit: upload_limit }));
+    }
+
+    // The image decoder cannot read animated WebP, they are sent as is
+    if format.is_image() && format != MediaFormat::AnimatedWebp {
+        let dimensions = image::io::Reader::open(&emote.path)
+                            .and_then(|reader| reader.with_guessed_format())
+                            .map_err(|err| err.to_string())
+                            .and_then(|reader| reader.into_dimensions().map_err(|err| err.to_string()));
+        match dimensions {
+            Ok((width, height)) if width > 0 && height > 0 => {},
+            Ok((width, height)) => return Err(invalid(format, Problem::Unreadable(format!("invalid dimensions {}x{}", width, height)))),
+            Err(err) => return Err(invalid(format, Problem::Unreadable(err))),
+        };
+    }
+
+    // The whole file is read, it is within the upload limit
+    if format.is_audio() {
+        let bytes = fs::read(&emote.path).map_err(|err| invalid(format, Problem::Unreadable(err.to_string())))?;
+        match media::audio_duration(&bytes) {
+            Some(duration) if duration > 0.0 => {},
+            Some(_) => return Err(invalid(format, Problem::Unreadable("the sound has no duration".into()))),
+            None => return Err(invalid(format, Problem::Unreadable("invalid audio headers".into()))),
+        };
+    }
+
+    Ok(())
+}
@@ -1,7 +1,38 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    collections::HashMap,
+};
 
-use crate::EmoteManager;
+use crate::{
+    EmoteManager,
+    bot::User,
+    stats::UsageStats,
+};
+
+use actix_web::{ http, HttpRequest };
 
 pub struct Data {
+    pub users: Vec<User>,
+    pub editors: Vec<u64>, // Discord ids of the users allowed to add and remove emotes of the shared library
     pub emote_mngr: Arc<EmoteManager>,
+    pub private_emotes: HashMap<u64, Arc<EmoteManager>>, // By Discord id
+    pub stats: Arc<UsageStats>,
+}
+
+impl Data {
+    /// The user whose `www_key` is given as an `Authorization: Bearer` header.
+    /// The key is never read from the URL, which ends up in the access log and the `Referer` of other sites.
+    pub fn authenticate(&self, req: &HttpRequest) -> Option<&User> {
+        let key = req.headers()
+                    .get(http::header::AUTHORIZATION)
+                    .and_then(|val| val.to_str().ok())
+                    .and_then(|val| val.strip_prefix("Bearer "))?;
+
+        self.users.iter().find(|user| user.www_key.as_deref() == Some(key))
+    }
+
+    /// The private emotes of the authenticated user, if they have any.
+    pub fn private_emotes(&self, req: &HttpRequest) -> Option<&Arc<EmoteManager>> {
+        self.authenticate(req).and_then(|user| self.private_emotes.get(&user.discord_id))
+    }
 }
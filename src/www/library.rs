@@ -1,38 +1,123 @@
 use std::{
+    sync::Arc,
     ffi::OsString,
-    path::PathBuf,
-    collections::HashMap,
+    path::Path,
 };
 
-use super::Data;
+use super::{ Data, EMOTES_ROUTE, PRIVATE_EMOTES_ROUTE };
+use crate::{
+    sources,
+    config::TextEmote,
+    emote_manager::{ self, EmoteManager },
+    stats::EmoteCount,
+    Error, ErrorKind, Result,
+};
 
 use serde::{ Serialize, Deserialize };
-use actix_web::{ web, http, HttpRequest, HttpResponse };
+use futures::{ future, Future, Stream };
+use actix_web::{ web, http, error::BlockingError, Either, HttpRequest, HttpResponse };
+use actix_files::NamedFile;
+use actix_multipart::{ Field, Multipart };
 
 #[derive(Serialize, Default)]
 struct Library(pub Vec<List>);
 
 impl Library {
-    pub fn get_list_for_type(&mut self, t: &str) -> Option<&mut List> {
-        self.0.iter_mut().find(|list| list.type_name == t)
+    /// Lists the emotes of a library by category, in the order of the configuration.
+    /// The files of private emotes are served under their own route.
+    pub fn build(mngr: &EmoteManager, private: bool) -> Result<Self> {
+        let mut library = Library(
+            mngr.categories()
+                .iter()
+                .filter(|category| category.palette)
+                .map(|category| List {
+                    type_name: if private { format!("{} (private)", category.display_name) } else { category.display_name.clone() },
+                    category: category.directory.clone(),
+                    private,
+                    emotes: Vec::new(),
+                    packs: Vec::new(),
+                })
+                .collect()
+        );
+
+        let route = if private { PRIVATE_EMOTES_ROUTE } else { EMOTES_ROUTE };
+        for emote in mngr.emotes()?.iter() {
+            let path = match emote.path.strip_prefix(mngr.assets_directory()) {
+                Ok(path) => path,
+                Err(_err) => continue,
+            };
+
+            if let Some(list) = library.get_list_for_category(&emote.category) {
+                list.push(Emote::with_route(emote, route, path));
+            }
+        }
+        library.0.retain(|list| !list.emotes.is_empty() || !list.packs.is_empty());
+        Ok(library)
+    }
+
+    pub fn get_list_for_category(&mut self, category: &str) -> Option<&mut List> {
+        self.0.iter_mut().find(|list| list.category == category)
     }
 }
 
 #[derive(Serialize)]
 struct List {
     pub type_name: String,
-    pub emotes: Vec<Emote>,
+    pub category: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool, // Private emotes of the authenticated user
+    pub emotes: Vec<Emote>, // Emotes that are not in a pack
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub packs: Vec<Pack>,
+}
+
+impl List {
+    pub fn push(&mut self, emote: Emote) {
+        match emote.pack.clone() {
+            Some(pack_name) => match self.packs.iter_mut().find(|pack| pack.name == pack_name) {
+                Some(pack) => pack.emotes.push(emote),
+                None => self.packs.push(Pack {
+                    name: pack_name,
+                    emotes: vec![emote],
+                }),
+            },
+            None => self.emotes.push(emote),
+        };
+    }
 }
 
 #[derive(Serialize)]
-pub struct Emote {
+struct Pack {
     pub name: String,
+    pub emotes: Vec<Emote>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Emote {
+    pub name: String, // Qualified with the pack, e.g. `pepe/sad`
     pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pack: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
 }
 
 impl Emote {
-    pub fn new(name: &str, path: &PathBuf) -> Self {
-        let mut url = OsString::new();
+    /// `path` is relative to the emotes directory.
+    pub fn new(emote: &emote_manager::Emote, path: &Path) -> Self {
+        Self::with_route(emote, EMOTES_ROUTE, path)
+    }
+
+    fn with_route(emote: &emote_manager::Emote, route: &str, path: &Path) -> Self {
+        let mut url = OsString::from(route);
         for component in path.components() {
             url.push("/");
             url.push(component);
@@ -40,48 +125,61 @@ impl Emote {
         let url = url.to_string_lossy();
 
         Self {
-            name: name.to_string(),
+            name: emote.qualified_name(),
             url: url.to_string(),
+            pack: emote.pack.clone(),
+            display_name: emote.meta.display_name.clone(),
+            aliases: emote.meta.aliases.clone(),
+            tags: emote.meta.tags.clone(),
+            description: emote.meta.description.clone(),
+            text: None,
+        }
+    }
+
+    pub fn from_text_emote(text_emote: &TextEmote) -> Self {
+        let mut triggers = text_emote.triggers.iter().cloned();
+
+        Self {
+            name: triggers.next().unwrap_or_default(),
+            aliases: triggers.collect(),
+            text: Some(text_emote.text.clone()),
+            ..Default::default()
         }
     }
 }
 
+/// The private emotes of the authenticated user come first, in their own lists.
 #[get("/library")]
-pub fn library(_req: HttpRequest, data: web::Data<Data>) -> HttpResponse {
-    let mut dir_to_type_bind = HashMap::new();
-    dir_to_type_bind.insert("emojis".to_string(), "Emoji".to_string());
-    dir_to_type_bind.insert("gifs".to_string(), "GIF".to_string());
-    dir_to_type_bind.insert("sounds".to_string(), "Sound".to_string());
-
-    let mut library = Library::default();
-    for emote in data.emote_mngr.emotes() {
-        let dir = match emote.path.parent() {
-            Some(dir) => dir,
-            None => continue,
-        };
-        let dir = match dir.file_name() {
-            Some(dir) => dir,
-            None => continue,
-        };
-        let dir = match dir.to_str() {
-            Some(dir) => dir,
-            None => continue,
-        };
-        let default_type = String::from("");
-        let emote_type = dir_to_type_bind.get(dir).unwrap_or(&default_type);
-
-        let serializable_emote = Emote::new(&emote.name, &emote.path);
-        match library.get_list_for_type(emote_type) {
-            Some(list) => list.emotes.push(serializable_emote),
-            None => library.0.push(List {
-                type_name: emote_type.clone(),
-                emotes: vec![serializable_emote],
-            }),
-        };
-    }
+pub fn library(req: HttpRequest, data: web::Data<Data>) -> HttpResponse {
+    let shared = Library::build(&data.emote_mngr, false);
+    let private = data.private_emotes(&req).map(|mngr| Library::build(mngr, true)).transpose();
+    let mut library = match (shared, private) {
+        (Ok(shared), Ok(private)) => {
+            let mut library = private.unwrap_or_default();
+            library.0.extend(shared.0);
+            library
+        },
+        (Err(err), _) | (_, Err(err)) => {
+            log::error!("An error occurred (/library): {}", err);
+            return HttpResponse::InternalServerError()
+                                .body("An internal error occurred.");
+        },
+    };
+
+    library.0.push(List {
+        type_name: "Text".to_string(),
+        category: String::new(),
+        private: false,
+        emotes: data.emote_mngr.text_emotes().iter().map(Emote::from_text_emote).collect(),
+        packs: Vec::new(),
+    });
 
     for list in library.0.iter_mut() {
         list.emotes.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
+        list.packs.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
+        for pack in list.packs.iter_mut() {
+            pack.emotes.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
+        }
     }
 
     HttpResponse::Ok()
@@ -97,7 +195,11 @@ pub struct TwitchSearchType {
 
 #[get("/library/twitch")]
 pub fn library_twitch(search: web::Query<TwitchSearchType>, data: web::Data<Data>) -> HttpResponse {
-    let emotes = match data.emote_mngr.find_twitch_emote_urls(search.query.as_ref(), search.limit, false) {
+    let source = match data.emote_mngr.source(sources::TWITCH_SOURCE) {
+        Some(source) => source,
+        None => return HttpResponse::NotFound().body("The Twitch emote source is not configured."),
+    };
+    let emotes = match source.search(search.query.as_ref(), search.limit) {
         Ok(emotes) => emotes,
         Err(err) => {
             log::error!("An error occurred (/library/twitch): {}", err);
@@ -110,3 +212,228 @@ pub fn library_twitch(search: web::Query<TwitchSearchType>, data: web::Data<Data
         .set_header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
         .json(emotes)
 }
+
+#[derive(Deserialize, Debug)]
+pub struct StatsQuery {
+    pub days: Option<u32>, // All time if not set
+    pub user: Option<u64>, // Only the authenticated user can see their own counts
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    pub emotes: Vec<EmoteCount>,
+    pub unused: Vec<String>, // Emotes of the library that were not used during the period
+}
+
+#[get("/library/stats")]
+pub fn library_stats(req: HttpRequest, query: web::Query<StatsQuery>, data: web::Data<Data>) -> HttpResponse {
+    if let Some(user_id) = query.user {
+        match data.authenticate(&req) {
+            Some(user) if user.discord_id == user_id => {},
+            Some(_user) => return HttpResponse::Forbidden().body("You can only see your own usage counts."),
+            None => return HttpResponse::Unauthorized().body("Usage counts of a user require a valid key."),
+        };
+    }
+
+    let top = data.stats.top(query.days, query.user);
+    let library = data.emote_mngr.emotes();
+    let (mut emotes, library) = match (top, library) {
+        (Ok(top), Ok(library)) => (top, library),
+        (Err(err), _) | (_, Err(err)) => {
+            log::error!("An error occurred (/library/stats): {}", err);
+            return HttpResponse::InternalServerError()
+                                .body("An internal error occurred.");
+        },
+    };
+
+    let mut unused = library
+                        .iter()
+                        .map(|emote| emote.qualified_name())
+                        .filter(|name| !emotes.iter().any(|count| &count.name == name))
+                        .collect::<Vec<_>>();
+    unused.sort();
+    if let Some(limit) = query.limit {
+        emotes.truncate(limit);
+    }
+
+    HttpResponse::Ok()
+        .set_header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .json(Stats {
+            emotes,
+            unused,
+        })
+}
+
+/// Serves the files of the shared emotes.
+#[get("/emotes/{path:.*}")]
+pub fn emote(path: web::Path<String>, data: web::Data<Data>) -> Either<HttpResponse, NamedFile> {
+    emote_file(&data.emote_mngr, &path, EMOTES_ROUTE)
+}
+
+/// Serves the files of the private emotes to the user they belong to.
+#[get("/private/{path:.*}")]
+pub fn private_emote(req: HttpRequest, path: web::Path<String>, data: web::Data<Data>) -> Either<HttpResponse, NamedFile> {
+    match data.private_emotes(&req) {
+        Some(mngr) => emote_file(mngr, &path, PRIVATE_EMOTES_ROUTE),
+        None => Either::A(HttpResponse::Unauthorized().body("Private emotes require a valid key.")),
+    }
+}
+
+/// Only the files of loaded emotes are served, which rules out paths leaving the directory
+/// as well as the manifest, the trash and the uploads being validated.
+fn emote_file(mngr: &EmoteManager, path: &str, route: &str) -> Either<HttpResponse, NamedFile> {
+    let path = mngr.assets_directory().join(path);
+    let is_emote = match mngr.emotes() {
+        Ok(emotes) => emotes.iter().any(|emote| emote.path == path),
+        Err(err) => {
+            log::error!("An error occurred ({}): {}", route, err);
+            return Either::A(HttpResponse::InternalServerError().body("An internal error occurred."));
+        },
+    };
+    if !is_emote {
+        return Either::A(HttpResponse::NotFound().body("No such emote."));
+    }
+
+    match NamedFile::open(&path) {
+        Ok(file) => Either::B(file),
+        Err(err) => {
+            log::error!("An error occurred ({}): {}", route, err);
+            Either::A(HttpResponse::InternalServerError().body("An internal error occurred."))
+        },
+    }
+}
+
+/// The fields of an emote upload form.
+#[derive(Default)]
+struct Upload {
+    name: Option<String>,
+    category: Option<String>, // The first category that lists the format of the file by default
+    private: bool, // Whether the emote goes into the private emotes of the user instead of the shared library
+    file: Option<(Vec<u8>, String)>, // Bytes and content type
+}
+
+impl Upload {
+    fn set_field(mut self, (name, content_type, bytes): (String, String, Vec<u8>)) -> Self {
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim().to_owned();
+        match name.as_str() {
+            "name" => self.name = Some(text(&bytes)),
+            "category" => self.category = Some(text(&bytes)).filter(|category| !category.is_empty()),
+            "private" => self.private = matches!(text(&bytes).as_str(), "true" | "on" | "1"),
+            "file" => self.file = Some((bytes, content_type)),
+            _ => {},
+        };
+        self
+    }
+}
+
+/// Reads a field of a multipart form, which may not be larger than `limit` bytes.
+fn read_field(field: Field, limit: u64) -> impl Future<Item = (String, String, Vec<u8>), Error = actix_web::Error> {
+    let name = field.content_disposition()
+                    .and_then(|disposition| disposition.get_name().map(str::to_owned))
+                    .unwrap_or_default();
+    let content_type = field.content_type().to_string();
+
+    field
+        .map_err(actix_web::Error::from)
+        .fold(Vec::new(), move |mut bytes, chunk| {
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() as u64 > limit {
+                Err(actix_web::error::ErrorPayloadTooLarge(format!("The file is larger than the upload limit of {} bytes.", limit)))
+            } else {
+                Ok(bytes)
+            }
+        })
+        .map(move |bytes| (name, content_type, bytes))
+}
+
+/// Rejected edits are reported to the user, other errors are logged.
+fn edit_error_response(route: &str, err: Error) -> HttpResponse {
+    match err.kind() {
+        ErrorKind::EditLibrary => HttpResponse::BadRequest().body(err.to_string()),
+        _ => {
+            log::error!("An error occurred ({}): {}", route, err);
+            HttpResponse::InternalServerError().body("An internal error occurred.")
+        },
+    }
+}
+
+/// Adds an emote from a multipart form with the `name`, `file` and optional `category` and `private` fields.
+/// The emote is validated and loaded like the ones added with `s.add`, and returned as listed in `/library`.
+/// Only editors may add emotes to the shared library, any user can add to their private emotes.
+#[post("/library/emotes")]
+pub fn add_emote(req: HttpRequest, multipart: Multipart, data: web::Data<Data>) -> Box<dyn Future<Item = HttpResponse, Error = actix_web::Error>> {
+    let user_id = match data.authenticate(&req) {
+        Some(user) => user.discord_id,
+        None => return Box::new(future::ok(HttpResponse::Unauthorized().body("Adding emotes requires a valid key."))),
+    };
+    let is_editor = data.editors.contains(&user_id);
+    let upload_limit = data.emote_mngr.upload_limit();
+
+    Box::new(multipart
+        .map_err(actix_web::Error::from)
+        .and_then(move |field| read_field(field, upload_limit))
+        .fold(Upload::default(), |upload, field| Ok::<_, actix_web::Error>(upload.set_field(field)))
+        .and_then(move |upload| {
+            let (mngr, private) = if upload.private {
+                match data.private_emotes.get(&user_id) {
+                    Some(mngr) => (mngr.clone(), true),
+                    None => return future::Either::A(future::ok(HttpResponse::BadRequest().body("You have no private emotes."))),
+                }
+            } else if is_editor {
+                (data.emote_mngr.clone(), false)
+            } else {
+                return future::Either::A(future::ok(HttpResponse::Forbidden().body("You are not allowed to add emotes to the shared library.")));
+            };
+            let (name, (bytes, content_type)) = match (upload.name, upload.file) {
+                (Some(name), Some(file)) => (name, file),
+                _ => return future::Either::A(future::ok(HttpResponse::BadRequest().body("The name and file fields are required."))),
+            };
+
+            // Writing and validating the file blocks, it is done on the thread pool
+            let category = upload.category;
+            future::Either::B(web::block(move || -> Result<Emote> {
+                let emote = mngr.add_emote(&name, category.as_deref(), bytes, Some(&content_type))?;
+                let path = emote.path.strip_prefix(mngr.assets_directory()).map_err(|err| Error::from(ErrorKind::EditLibrary, err))?;
+                Ok(Emote::with_route(&emote, if private { PRIVATE_EMOTES_ROUTE } else { EMOTES_ROUTE }, path))
+            }).then(|res| Ok::<_, actix_web::Error>(match res {
+                Ok(emote) => HttpResponse::Created()
+                                .set_header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                                .json(emote),
+                Err(BlockingError::Error(err)) => edit_error_response("/library/emotes", err),
+                Err(BlockingError::Canceled) => HttpResponse::InternalServerError().body("An internal error occurred."),
+            })))
+        }))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RemoveQuery {
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// Moves an emote to the trash, like `s.rm`. Only editors may remove emotes from the shared library.
+#[delete("/library/emotes/{name:.*}")]
+pub fn remove_emote(req: HttpRequest, name: web::Path<String>, query: web::Query<RemoveQuery>, data: web::Data<Data>) -> HttpResponse {
+    let user_id = match data.authenticate(&req) {
+        Some(user) => user.discord_id,
+        None => return HttpResponse::Unauthorized().body("Removing emotes requires a valid key."),
+    };
+    let mngr: &Arc<EmoteManager> = if query.private {
+        match data.private_emotes.get(&user_id) {
+            Some(mngr) => mngr,
+            None => return HttpResponse::BadRequest().body("You have no private emotes."),
+        }
+    } else if data.editors.contains(&user_id) {
+        &data.emote_mngr
+    } else {
+        return HttpResponse::Forbidden().body("You are not allowed to remove emotes from the shared library.");
+    };
+
+    match mngr.remove_emote(&name) {
+        Ok(_trash_path) => HttpResponse::NoContent()
+                                .set_header(http::header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                                .finish(),
+        Err(err) => edit_error_response("/library/emotes", err),
+    }
+}
@@ -3,28 +3,47 @@ mod index;
 mod palette;
 pub mod library;
 
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    collections::HashMap,
+};
 
 use crate::{
     Result,
     EmoteManager,
+    bot::User,
     config::WwwConfig,
+    stats::UsageStats,
 };
 use data::Data;
 
 use actix_web::{ middleware, App, HttpServer };
 
-pub fn start(config: &WwwConfig, emote_mngr: Arc<EmoteManager>) -> Result<()> {
+/// Route under which the emotes directory is served.
+pub const EMOTES_ROUTE: &str = "/emotes";
+/// Route under which the private emotes of the authenticated user are served.
+pub const PRIVATE_EMOTES_ROUTE: &str = "/private";
+
+pub fn start(config: &WwwConfig, users: Vec<User>, editors: Vec<u64>, emote_mngr: Arc<EmoteManager>, private_emotes: HashMap<u64, Arc<EmoteManager>>, stats: Arc<UsageStats>) -> Result<()> {
     HttpServer::new(move || {
         App::new()
             .data(Data {
+                users: users.clone(),
+                editors: editors.clone(),
                 emote_mngr: emote_mngr.clone(),
+                private_emotes: private_emotes.clone(),
+                stats: stats.clone(),
             })
             .wrap(middleware::Logger::default())
-            .service(actix_files::Files::new("/assets", "assets"))
+            .service(actix_files::Files::new("/assets/www", "assets/www")) // The files of the pages only, emote files go through `library::emote`
             .service(index::index)
             .service(library::library)
             .service(library::library_twitch)
+            .service(library::library_stats)
+            .service(library::emote)
+            .service(library::private_emote)
+            .service(library::add_emote)
+            .service(library::remove_emote)
             .service(palette::palette)
     })
     .disable_signals()